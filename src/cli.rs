@@ -0,0 +1,47 @@
+use globalip_memo::{ErrorKind, Result};
+
+#[derive(Debug, Default)]
+pub struct Args {
+    profiles: Vec<String>,
+    daemon: bool,
+    scoreboard: bool,
+}
+
+impl Args {
+    pub fn parse<I>(args: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut parsed = Args::default();
+        for arg in args {
+            match arg.as_str() {
+                "--daemon" => {
+                    parsed.daemon = true;
+                    continue;
+                }
+                "--scoreboard" => {
+                    parsed.scoreboard = true;
+                    continue;
+                }
+                _ => {}
+            }
+            if arg.starts_with('-') {
+                return Err(err!("Args::parse: Unknown option - {}", arg).with_kind(ErrorKind::Config));
+            }
+            parsed.profiles.push(arg);
+        }
+        Ok(parsed)
+    }
+
+    pub fn profiles(&self) -> &Vec<String> {
+        &self.profiles
+    }
+
+    pub fn daemon(&self) -> bool {
+        self.daemon
+    }
+
+    pub fn scoreboard(&self) -> bool {
+        self.scoreboard
+    }
+}
@@ -7,20 +7,69 @@ pub struct Error {
 
 #[derive(Debug)]
 struct Inner {
+    kind: ErrorKind,
     source: ErrorSource,
     message: String,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorKind {
+    Other,
+    Config,
+    Fetch,
+    Consensus,
+    Output,
+    Locked,
+    Cancelled,
+    /// Every method was skipped by its `min_interval`.
+    Throttled,
+}
+
 #[derive(Debug)]
 pub enum ErrorSource {
     None,
     Io(std::io::Error),
     Json(serde_json::Error),
+    Xml(roxmltree::Error),
     Http(reqwest::Error),
     Regex(regex::Error),
     Addr(std::net::AddrParseError),
+    Tls(openssl::error::ErrorStack),
+    Ssl(openssl::ssl::Error),
+    Pin(PinMismatch),
+}
+
+#[derive(Debug)]
+pub struct PinMismatch {
+    host: String,
+    found: Vec<String>,
+}
+
+impl PinMismatch {
+    pub fn new<S>(host: S, found: Vec<String>) -> Self
+    where
+        S: Into<String>,
+    {
+        PinMismatch {
+            host: host.into(),
+            found,
+        }
+    }
 }
 
+impl fmt::Display for PinMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "No pinned public key matched for {} - found: {}",
+            self.host,
+            self.found.join(", ")
+        )
+    }
+}
+
+impl error::Error for PinMismatch {}
+
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.write_str(self.inner.message.as_str())
@@ -33,9 +82,13 @@ impl error::Error for Error {
             ErrorSource::None => None,
             ErrorSource::Io(ref e) => Some(e),
             ErrorSource::Json(ref e) => Some(e),
+            ErrorSource::Xml(ref e) => Some(e),
             ErrorSource::Http(ref e) => Some(e),
             ErrorSource::Regex(ref e) => Some(e),
             ErrorSource::Addr(ref e) => Some(e),
+            ErrorSource::Tls(ref e) => Some(e),
+            ErrorSource::Ssl(ref e) => Some(e),
+            ErrorSource::Pin(ref e) => Some(e),
         }
     }
 }
@@ -47,11 +100,21 @@ impl Error {
     {
         Error {
             inner: Box::new(Inner {
+                kind: ErrorKind::Other,
                 message: message.into(),
                 source: source,
             }),
         }
     }
+
+    pub fn with_kind(mut self, kind: ErrorKind) -> Self {
+        self.inner.kind = kind;
+        self
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.inner.kind
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
@@ -83,6 +146,15 @@ macro_rules! err_json {
     };
 }
 
+macro_rules! err_xml {
+    ($e:expr, $msg:expr) => {
+        $crate::error::Error::new($msg, $crate::error::ErrorSource::Xml($e))
+    };
+    ($e:expr, $f:expr, $($arg:expr),+) => {
+        err_xml!($e, format!($f, $($arg,)+))
+    };
+}
+
 macro_rules! err_http {
     ($e:expr, $msg:expr) => {
         $crate::error::Error::new($msg, $crate::error::ErrorSource::Http($e))
@@ -110,6 +182,33 @@ macro_rules! err_addr {
     };
 }
 
+macro_rules! err_tls {
+    ($e:expr, $msg:expr) => {
+        $crate::error::Error::new($msg, $crate::error::ErrorSource::Tls($e))
+    };
+    ($e:expr, $f:expr, $($arg:expr),+) => {
+        err_tls!($e, format!($f, $($arg,)+))
+    };
+}
+
+macro_rules! err_ssl {
+    ($e:expr, $msg:expr) => {
+        $crate::error::Error::new($msg, $crate::error::ErrorSource::Ssl($e))
+    };
+    ($e:expr, $f:expr, $($arg:expr),+) => {
+        err_ssl!($e, format!($f, $($arg,)+))
+    };
+}
+
+macro_rules! err_pin {
+    ($e:expr, $msg:expr) => {
+        $crate::error::Error::new($msg, $crate::error::ErrorSource::Pin($e))
+    };
+    ($e:expr, $f:expr, $($arg:expr),+) => {
+        err_pin!($e, format!($f, $($arg,)+))
+    };
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
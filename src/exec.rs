@@ -0,0 +1,157 @@
+use crate::env::{ExecOptions, IpVersion};
+use crate::error::Result;
+use std::io::{self, Read};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const IP_VERSION_ENV: &str = "GLOBALIP_MEMO_IP_VERSION";
+const POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Reads the pipe to the end on its own thread, which lives as long as some process holds the pipe open.
+fn read_pipe<R: Read + Send + 'static>(pipe: Option<R>) -> Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_end(&mut buf);
+        }
+        let _ = sender.send(buf);
+    });
+    receiver
+}
+
+/// Kills the process group of the child, so processes it started go with it.
+fn kill_group(child: &mut Child) {
+    unsafe {
+        libc::killpg(child.id() as libc::pid_t, libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+fn timed_out(name: &str, timeout: Duration) -> crate::error::Error {
+    err!("run_group: Command timed out after {}s - {}", timeout.as_secs(), name)
+}
+
+/// Runs the command and returns its exit status, stdout and stderr, failing after the timeout.
+///
+/// The command leads a process group of its own, which is killed on timeout,
+/// including when processes it left behind keep its output open.
+pub fn run_group(command: &mut Command, name: &str, timeout: Duration) -> Result<(ExitStatus, Vec<u8>, Vec<u8>)> {
+    command.stdin(Stdio::null()).stdout(Stdio::piped()).stderr(Stdio::piped());
+    unsafe {
+        command.pre_exec(|| {
+            if libc::setpgid(0, 0) == 0 {
+                Ok(())
+            } else {
+                Err(io::Error::last_os_error())
+            }
+        });
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| err_io!(e, "run_group: Failed to run command - {}", name))?;
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break status,
+            Ok(None) if Instant::now() < deadline => thread::sleep(POLL_INTERVAL),
+            Ok(None) => {
+                kill_group(&mut child);
+                return Err(timed_out(name, timeout));
+            }
+            Err(e) => {
+                kill_group(&mut child);
+                return Err(err_io!(e, "run_group: Failed to wait for command - {}", name));
+            }
+        }
+    };
+    let remaining = || deadline.saturating_duration_since(Instant::now());
+    match stdout
+        .recv_timeout(remaining())
+        .and_then(|stdout| stderr.recv_timeout(remaining()).map(|stderr| (status, stdout, stderr)))
+    {
+        Ok(output) => Ok(output),
+        Err(_) => {
+            kill_group(&mut child);
+            Err(timed_out(name, timeout))
+        }
+    }
+}
+
+/// Runs the command in `dir` and returns its stdout, failing on a non-zero exit or after the timeout.
+pub fn run(options: &ExecOptions, ip_version: &IpVersion, dir: &Path) -> Result<String> {
+    let mut command = Command::new(options.command());
+    command
+        .args(options.args())
+        .envs(options.env())
+        .env(IP_VERSION_ENV, format!("{}", ip_version));
+    if !dir.as_os_str().is_empty() {
+        command.current_dir(dir);
+    }
+    debug!("run: {:?}", &command);
+    let (status, stdout, stderr) = run_group(&mut command, options.command(), options.timeout())?;
+    if !stderr.is_empty() {
+        debug!("run: stderr - {}", String::from_utf8_lossy(&stderr).trim_end());
+    }
+    if !status.success() {
+        return Err(err!("run: Command {} failed - {}", options.command(), status));
+    }
+    String::from_utf8(stdout)
+        .map_err(|e| err!("run: Command output is not UTF-8 - {} - {}", options.command(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(script: &str, timeout: u64) -> ExecOptions {
+        serde_json::from_value(json!({
+            "command": "/bin/sh",
+            "args": ["-c", script],
+            "env": {"WAN_ADDR": "203.0.113.7"},
+            "timeout": timeout
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_run() {
+        let dir = Path::new("");
+        let output = run(&options("echo \"$GLOBALIP_MEMO_IP_VERSION $WAN_ADDR\"", 5), &IpVersion::IPv4, dir).unwrap();
+        assert_eq!(output, format!("{} 203.0.113.7\n", IpVersion::IPv4));
+
+        assert!(run(&options("echo 203.0.113.7; exit 3", 5), &IpVersion::IPv4, dir).is_err());
+
+        let started = Instant::now();
+        assert!(run(&options("sleep 10", 1), &IpVersion::IPv4, dir).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let output = run(&options("pwd", 5), &IpVersion::IPv4, Path::new("/")).unwrap();
+        assert_eq!(output, "/\n");
+    }
+
+    #[test]
+    fn test_run_process_group() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-exec-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let marker = dir.join("marker");
+
+        // The grandchild keeps stdout open after the command exits.
+        let script = format!("echo 203.0.113.7; (sleep 2; touch {}) &", marker.display());
+        let started = Instant::now();
+        assert!(run(&options(&script, 1), &IpVersion::IPv4, &dir).is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let script = format!("(sleep 2; touch {}) & sleep 10", marker.display());
+        assert!(run(&options(&script, 1), &IpVersion::IPv4, &dir).is_err());
+        thread::sleep(Duration::from_secs(3));
+        assert!(!marker.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
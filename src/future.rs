@@ -0,0 +1,242 @@
+use crate::env::{Config, FetchOrder, IpVersion, Method, Profile, Strategy};
+use crate::error::{Error, ErrorKind, Result};
+use crate::exec;
+use crate::logging;
+use crate::resolver::{self, FetchResult, Resolution};
+use crate::score::Scoreboard;
+use crate::util;
+use futures::future::{self, Either, Loop};
+use futures::sync::oneshot;
+use futures::task::AtomicTask;
+use futures::{Future, Poll};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+type FetchFuture = Box<dyn Future<Item = FetchResult, Error = Error> + Send>;
+
+#[derive(Debug, Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    task: AtomicTask,
+}
+
+/// Cancels a `ResolveFuture`; dropping the handle leaves the future running.
+#[derive(Debug, Clone)]
+pub struct CancelHandle {
+    state: Arc<CancelState>,
+}
+
+impl CancelHandle {
+    /// Makes the future fail with `ErrorKind::Cancelled` and drops the requests in flight.
+    pub fn cancel(&self) {
+        self.state.cancelled.store(true, Ordering::SeqCst);
+        self.state.task.notify();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.state.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Future returned by `Resolver::resolve_async`.
+pub struct ResolveFuture {
+    inner: Box<dyn Future<Item = Resolution, Error = Error> + Send>,
+    state: Arc<CancelState>,
+}
+
+impl Future for ResolveFuture {
+    type Item = Resolution;
+    type Error = Error;
+
+    fn poll(&mut self) -> Poll<Resolution, Error> {
+        self.state.task.register();
+        if self.state.cancelled.load(Ordering::SeqCst) {
+            return Err(err!("ResolveFuture::poll: Resolution cancelled").with_kind(ErrorKind::Cancelled));
+        }
+        self.inner.poll()
+    }
+}
+
+/// Runs a blocking call on its own thread so waiting for it does not block the executor.
+pub(crate) fn blocking<T, F>(f: F) -> Box<dyn Future<Item = T, Error = Error> + Send>
+where
+    T: Send + 'static,
+    F: FnOnce() -> Result<T> + Send + 'static,
+{
+    let (sender, receiver) = oneshot::channel();
+    thread::spawn(move || {
+        let _ = sender.send(f());
+    });
+    Box::new(receiver.then(|result| match result {
+        Ok(result) => result,
+        Err(_) => Err(err!("blocking: Worker thread stopped")),
+    }))
+}
+
+fn exec_async(method: Arc<Method>, ip_version: IpVersion, dir: PathBuf) -> Box<dyn Future<Item = String, Error = Error> + Send> {
+    blocking(move || match method.as_ref() {
+        Method::Exec { exec, .. } => exec::run(exec, &ip_version, &dir),
+        method => Err(err!("exec_async: Not an exec method - {}", method.url())),
+    })
+}
+
+fn fetch_one(config: &Config, profile: &Profile, board: Option<&Scoreboard>, method: &Arc<Method>) -> FetchFuture {
+    let body = method.http().map(|http| {
+        let _url = logging::with_url(method.url());
+        util::get_body_async(method.url(), http, &resolver::transport(http, profile, config))
+    });
+    let method = method.clone();
+    let weight = resolver::weight(config, profile, board, &method);
+    let ip_version = profile.ip_version().clone();
+    let dir = profile.dir().to_path_buf();
+    Box::new(future::lazy(move || {
+        let started = Instant::now();
+        let body: Box<dyn Future<Item = String, Error = Error> + Send> = match (body, method.as_ref()) {
+            (Some(body), _) => Box::new(future::result(body).flatten()),
+            (None, Method::Exec { .. }) => exec_async(method.clone(), ip_version.clone(), dir.clone()),
+            (None, Method::File { path, .. }) => Box::new(future::result(util::read_file(path, method.max_age()))),
+            (None, Method::Custom { provider, .. }) => provider.clone().fetch_async(&ip_version),
+            (None, method) => Box::new(future::err(err!("fetch_one: HTTP options not found - {}", method.url()))),
+        };
+        body.then(move |body| {
+            let result = body.and_then(|body| resolver::parse_body(&method, &ip_version, body));
+            let latency = started.elapsed();
+            let _url = logging::with_url(method.url());
+            debug!("fetch: result - {:?}, latency - {:?}, method - {:?}", &result, latency, method);
+            Ok(FetchResult { method, weight, result, latency })
+        })
+    }))
+}
+
+/// Queries the methods one after another until the target weight is reached.
+fn fetch_sequential(
+    pending: Vec<FetchFuture>,
+    expected: Option<IpAddr>,
+    target_weight: f64,
+) -> impl Future<Item = Vec<FetchResult>, Error = Error> + Send {
+    let total = pending.len();
+    future::loop_fn((Vec::with_capacity(total), pending.into_iter()), move |(mut list, mut rest)| {
+        match rest.next() {
+            None => Either::A(future::ok(Loop::Break(list))),
+            Some(fetch) => Either::B(fetch.map(move |result| {
+                list.push(result);
+                match resolver::target_reached(&list, expected.as_ref(), target_weight) {
+                    Some((addr, weight)) => {
+                        resolver::report_target_reached(target_weight, &addr, weight, list.len(), total);
+                        Loop::Break(list)
+                    }
+                    None => Loop::Continue((list, rest)),
+                }
+            })),
+        }
+    })
+}
+
+pub(crate) fn resolve(
+    config: &Config,
+    profile: &Profile,
+    board: Option<&Scoreboard>,
+    previous: Option<IpAddr>,
+) -> (ResolveFuture, CancelHandle) {
+    let mut pending: Vec<FetchFuture> = profile
+        .methods()
+        .iter()
+        .map(|method| fetch_one(config, profile, board, method))
+        .collect();
+    let list: Box<dyn Future<Item = Vec<FetchResult>, Error = Error> + Send> = match profile.strategy() {
+        Strategy::All => Box::new(future::join_all(pending)),
+        Strategy::Sequential { order, target_weight, agree_with_previous } => {
+            if *order == FetchOrder::Random {
+                resolver::shuffle(&mut pending, resolver::random_seed());
+            }
+            let expected = if *agree_with_previous { previous } else { None };
+            Box::new(fetch_sequential(pending, expected, *target_weight))
+        }
+    };
+    let profile = profile.clone();
+    let inner = list.and_then(move |list| -> Result<Resolution> {
+        let (methods, consensus) = resolver::vote(&list, &profile, previous.as_ref());
+        resolver::conclude(&profile, methods, consensus)
+    });
+    let state = Arc::new(CancelState::default());
+    let handle = CancelHandle { state: state.clone() };
+    (ResolveFuture { inner: Box::new(inner), state }, handle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::{Ipv4Addr, TcpListener};
+    use std::thread;
+    use std::time::Duration;
+
+    fn serve(responses: Vec<Option<&'static str>>) -> Vec<String> {
+        responses
+            .into_iter()
+            .map(|body| {
+                let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+                let url = format!("http://{}/", listener.local_addr().unwrap());
+                thread::spawn(move || {
+                    let (mut stream, _) = listener.accept().unwrap();
+                    let mut buf = [0u8; 1024];
+                    let _ = stream.read(&mut buf);
+                    match body {
+                        Some(body) => {
+                            let response = format!(
+                                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                body.len(),
+                                body
+                            );
+                            stream.write_all(response.as_bytes()).unwrap();
+                        }
+                        None => thread::sleep(Duration::from_secs(10)),
+                    }
+                });
+                url
+            })
+            .collect()
+    }
+
+    fn build_config(urls: &[String]) -> Config {
+        let methods = urls
+            .iter()
+            .map(|url| format!(r#"{{"type": "plain", "url": "{}", "proxy": {{"type": "none"}}}}"#, url))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let json = format!(
+            r#"{{"profiles": {{"default": {{"ip_version": "ipv4", "methods": [{}], "output": "globalip.txt"}}}}}}"#,
+            methods
+        );
+        Config::from_reader(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_resolve_async() {
+        let mut runtime = tokio::runtime::Runtime::new().unwrap();
+
+        let config = build_config(&serve(vec![Some("203.0.113.7\n"), Some("203.0.113.7"), Some("198.51.100.9")]));
+        let profile = config.profile("default").unwrap();
+        let (future, _) = resolver::Resolver::new(&config, &profile).resolve_async();
+        let resolution = runtime.block_on(future).unwrap();
+        assert_eq!(resolution.addr, IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)));
+        assert_eq!(resolution.methods.len(), 3);
+        assert!(resolution.is_split());
+
+        let config = build_config(&serve(vec![None]));
+        let profile = config.profile("default").unwrap();
+        let (future, handle) = resolver::Resolver::new(&config, &profile).resolve_async();
+        let canceller = handle.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            canceller.cancel();
+        });
+        let e = runtime.block_on(future).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Cancelled);
+        assert!(handle.is_cancelled());
+    }
+}
@@ -0,0 +1,101 @@
+use globalip_memo::__private::{run_group, Env, Hook, HookEvent};
+use globalip_memo::{Candidate, Profile, Result};
+use log::Level;
+use std::net::IpAddr;
+use std::process::Command;
+
+const PROFILE_ENV: &str = "GLOBALIP_MEMO_PROFILE";
+const IP_VERSION_ENV: &str = "GLOBALIP_MEMO_IP_VERSION";
+const ADDR_ENV: &str = "GLOBALIP_MEMO_ADDR";
+const OLD_ADDR_ENV: &str = "GLOBALIP_MEMO_OLD_ADDR";
+const OUTPUT_ENV: &str = "GLOBALIP_MEMO_OUTPUT";
+const EVENT_ENV: &str = "GLOBALIP_MEMO_EVENT";
+const CANDIDATES_ENV: &str = "GLOBALIP_MEMO_CANDIDATES";
+
+fn execute(
+    env: &Env,
+    profile: &Profile,
+    hook: &Hook,
+    event: &HookEvent,
+    addr: &IpAddr,
+    old_addr: Option<&IpAddr>,
+    candidates: &[Candidate],
+) -> Result<()> {
+    let candidates = serde_json::to_string(candidates)
+        .map_err(|e| err_json!(e, "execute: Failed to serialize candidates"))?;
+    let mut command = Command::new(hook.command());
+    command
+        .args(hook.args())
+        .current_dir(env.dir())
+        .env(PROFILE_ENV, profile.name())
+        .env(IP_VERSION_ENV, format!("{}", profile.ip_version()))
+        .env(ADDR_ENV, format!("{}", addr))
+        .env(OUTPUT_ENV, profile.output_path())
+        .env(EVENT_ENV, event.name())
+        .env(CANDIDATES_ENV, candidates);
+    match old_addr {
+        Some(old) => command.env(OLD_ADDR_ENV, format!("{}", old)),
+        None => command.env_remove(OLD_ADDR_ENV),
+    };
+    debug!("execute: Running {} hook - {}", event.name(), hook.command());
+    let (status, stdout, stderr) = run_group(&mut command, hook.command(), hook.timeout())?;
+    for (name, output) in &[("stdout", stdout), ("stderr", stderr)] {
+        if !output.is_empty() {
+            debug!("execute: {} - {}", name, String::from_utf8_lossy(output).trim_end());
+        }
+    }
+    if status.success() {
+        info!("execute: Hook completed - {}", hook.command());
+        Ok(())
+    } else {
+        Err(err!("execute: Hook {} failed - {}", hook.command(), status))
+    }
+}
+
+pub fn run(
+    env: &Env,
+    profile: &Profile,
+    event: HookEvent,
+    addr: &IpAddr,
+    old_addr: Option<&IpAddr>,
+    candidates: &[Candidate],
+) {
+    for hook in profile.hooks().iter().filter(|hook| hook.on() == &event) {
+        if let Err(e) = execute(env, profile, hook, &event, addr, old_addr, candidates) {
+            log_error!(Level::Warn, &e, "run: Failed to run {:?} hook - {}", event, hook.command());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalip_memo::Config;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_execute_timeout() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-hook-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let text = r#"{
+            "methods": [{"type": "file", "path": "wan.txt"}],
+            "hooks": [
+                {"command": "/bin/sh", "args": ["-c", "echo $GLOBALIP_MEMO_ADDR > change.txt"]},
+                {"command": "/bin/sh", "args": ["-c", "sleep 10"], "timeout": 1}
+            ]
+        }"#;
+        let env = Env::from_config(dir.clone(), Config::from_reader(text.as_bytes()).unwrap()).unwrap();
+        let profile = env.select_profiles(&[]).unwrap()[0];
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let hooks = profile.hooks();
+        assert_eq!(hooks[0].timeout(), Duration::from_secs(60));
+        assert!(execute(&env, profile, &hooks[0], &HookEvent::Change, &addr, None, &[]).is_ok());
+        assert_eq!(fs::read_to_string(dir.join("change.txt")).unwrap().trim(), "192.0.2.1");
+
+        let started = Instant::now();
+        assert!(execute(&env, profile, &hooks[1], &HookEvent::Change, &addr, None, &[]).is_err());
+        assert!(started.elapsed() < Duration::from_secs(5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,409 @@
+//! Requests sent on a connection opened by this crate instead of reqwest,
+//! for the options reqwest cannot honour: TLS pins, `system_roots: false`
+//! and binding to an interface. The connection does not go through a proxy
+//! and redirects are not followed.
+
+use crate::env::{Auth, HttpOptions, IpVersion, Proxy};
+use crate::error::Result;
+use crate::net::{self, Transport};
+use crate::tls;
+use crate::util;
+use openssl::base64;
+use openssl::ssl::SslConnector;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Limit on the response head and on the body; an address is a few bytes.
+const MAX_RESPONSE: usize = 64 * 1024;
+
+/// Whether the request needs a connection the reqwest clients cannot set up.
+pub fn is_required(transport: &Transport) -> bool {
+    transport.interface.is_some() || transport.tls.filter(|options| tls::needs_verification(options)).is_some()
+}
+
+/// HTTP/1.1 request whose connection is verified against the pins and bound
+/// to the interface.
+pub struct Request {
+    url: String,
+    host: String,
+    port: u16,
+    connector: Option<SslConnector>,
+    pins: Vec<String>,
+    message: Vec<u8>,
+    expected_status: Vec<u16>,
+    ip_version: IpVersion,
+    bind_address: Option<IpAddr>,
+    interface: Option<String>,
+    timeout: Duration,
+}
+
+fn header_value<'a>(url: &str, value: &'a str) -> Result<&'a str> {
+    if value.contains(&['\r', '\n'][..]) {
+        return Err(err!("Request::new: Line break in header value - {}", url));
+    }
+    Ok(value)
+}
+
+impl Request {
+    pub fn new(url: &str, options: &HttpOptions, transport: &Transport) -> Result<Request> {
+        let parsed = reqwest::Url::parse(url).map_err(|e| err!("Request::new: Invalid url {} - {}", url, e))?;
+        let connector = match parsed.scheme() {
+            "https" => Some(tls::connector(transport.tls, url)?),
+            "http" if transport.tls.filter(|options| tls::needs_verification(options)).is_some() => {
+                return Err(err!("Request::new: Pins and disabled system roots require https - {}", url));
+            }
+            "http" => None,
+            scheme => return Err(err!("Request::new: Unsupported scheme {} - {}", scheme, url)),
+        };
+        match transport.proxy {
+            Some(Proxy::Http { .. }) | Some(Proxy::Socks5 { .. }) => {
+                return Err(err!("Request::new: The connection cannot go through a proxy - {}", url));
+            }
+            Some(Proxy::None) => {}
+            None | Some(Proxy::System) => {
+                if let Some(proxy) = util::system_proxy(url) {
+                    return Err(err!(
+                        "Request::new: The connection cannot go through the system proxy {}, set proxy to none or list the host in no_proxy - {}",
+                        util::redact_url(&proxy),
+                        url
+                    ));
+                }
+            }
+        }
+        let host = parsed
+            .host_str()
+            .ok_or_else(|| err!("Request::new: Host not found - {}", url))?;
+        let port = parsed.port_or_known_default().unwrap_or(443);
+        let authority = match parsed.port() {
+            Some(port) => format!("{}:{}", host, port),
+            None => host.to_owned(),
+        };
+        let target = match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_owned(),
+        };
+
+        if options.http_method().contains(|c: char| !c.is_ascii_alphabetic()) {
+            return Err(err!("Request::new: Invalid HTTP method {} - {}", options.http_method(), url));
+        }
+        let mut head = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", options.http_method(), target, authority);
+        for (name, value) in options.headers() {
+            head.push_str(&format!("{}: {}\r\n", header_value(url, name)?, header_value(url, value)?));
+        }
+        if let Some(user_agent) = options.user_agent() {
+            head.push_str(&format!("User-Agent: {}\r\n", header_value(url, user_agent)?));
+        }
+        match options.auth() {
+            Some(Auth::Basic { username, password }) => {
+                let credentials = format!("{}:{}", username, password.as_deref().unwrap_or(""));
+                head.push_str(&format!("Authorization: Basic {}\r\n", base64::encode_block(credentials.as_bytes())));
+            }
+            Some(Auth::Bearer { token }) => {
+                head.push_str(&format!("Authorization: Bearer {}\r\n", header_value(url, token)?));
+            }
+            None => {}
+        }
+        let body = options.body().unwrap_or("");
+        if options.body().is_some() {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("Accept-Encoding: identity\r\nConnection: close\r\n\r\n");
+        let mut message = head.into_bytes();
+        message.extend_from_slice(body.as_bytes());
+
+        Ok(Request {
+            url: url.to_owned(),
+            host: host.trim_start_matches('[').trim_end_matches(']').to_owned(),
+            port,
+            connector,
+            pins: transport.tls.map(|options| options.pins().clone()).unwrap_or_default(),
+            message,
+            expected_status: options.expected_status().clone(),
+            ip_version: transport.ip_version.clone(),
+            bind_address: transport.bind_address,
+            interface: transport.interface.map(|name| name.to_owned()),
+            timeout: options.timeout(),
+        })
+    }
+
+    /// Connects, sends the request and returns the response body.
+    pub fn send(&self) -> Result<String> {
+        let transport = Transport {
+            ip_version: &self.ip_version,
+            proxy: None,
+            tls: None,
+            bind_address: self.bind_address,
+            interface: self.interface.as_deref(),
+        };
+        let stream = net::connect(&self.host, self.port, &transport, self.timeout)?;
+        match self.connector {
+            Some(ref connector) => {
+                let stream = tls::handshake(connector, &self.host, stream, &self.pins, &self.url)?;
+                self.exchange(stream)
+            }
+            None => self.exchange(stream),
+        }
+    }
+
+    fn exchange<S: Read + Write>(&self, mut stream: S) -> Result<String> {
+        let url = self.url.as_str();
+        stream
+            .write_all(&self.message)
+            .and_then(|_| stream.flush())
+            .map_err(|e| err_io!(e, "Request::send: Failed to send request - {}", url))?;
+        let mut reader = BufReader::new(stream);
+        let status_line = read_line(&mut reader, url)?;
+        let status = status_line
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse::<u16>().ok())
+            .filter(|_| status_line.starts_with("HTTP/1."))
+            .ok_or_else(|| err!("Request::send: Invalid status line {} - {}", status_line.trim_end(), url))?;
+
+        let (mut length, mut chunked, mut head) = (None, false, status_line.len());
+        loop {
+            let line = read_line(&mut reader, url)?;
+            head += line.len();
+            if head > MAX_RESPONSE {
+                return Err(err!("Request::send: Response head exceeds {} bytes - {}", MAX_RESPONSE, url));
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            let mut parts = line.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_lowercase();
+            let value = parts.next().unwrap_or("").trim();
+            match name.as_str() {
+                "content-length" => {
+                    length = Some(value.parse::<usize>().map_err(|_| {
+                        err!("Request::send: Invalid Content-Length {} - {}", value, url)
+                    })?)
+                }
+                "transfer-encoding" => chunked = value.to_lowercase().ends_with("chunked"),
+                _ => {}
+            }
+        }
+
+        if (300..400).contains(&status) && !self.expected_status.contains(&status) {
+            return Err(err!("Request::send: Redirect status {} is not followed - {}", status, url));
+        }
+        util::check_status(url, &self.expected_status, status)?;
+        let body = if chunked {
+            read_chunked(&mut reader, url)?
+        } else {
+            if length.filter(|length| *length > MAX_RESPONSE).is_some() {
+                return Err(err!("Request::send: Response body exceeds {} bytes - {}", MAX_RESPONSE, url));
+            }
+            let limit = length.unwrap_or(MAX_RESPONSE + 1);
+            let mut body = Vec::new();
+            reader
+                .take(limit as u64)
+                .read_to_end(&mut body)
+                .map_err(|e| err_io!(e, "Request::send: Failed to read response body - {}", url))?;
+            if body.len() > MAX_RESPONSE {
+                return Err(err!("Request::send: Response body exceeds {} bytes - {}", MAX_RESPONSE, url));
+            }
+            if length.filter(|length| *length != body.len()).is_some() {
+                return Err(err!("Request::send: Response body truncated - {}", url));
+            }
+            body
+        };
+        Ok(String::from_utf8_lossy(&body).into_owned())
+    }
+}
+
+fn read_line<R: BufRead>(reader: &mut R, url: &str) -> Result<String> {
+    let mut line = String::new();
+    match reader.by_ref().take(MAX_RESPONSE as u64 + 1).read_line(&mut line) {
+        Ok(0) => Err(err!("Request::send: Connection closed - {}", url)),
+        Ok(n) if n > MAX_RESPONSE => Err(err!("Request::send: Line exceeds {} bytes - {}", MAX_RESPONSE, url)),
+        Ok(_) => Ok(line),
+        Err(e) => Err(err_io!(e, "Request::send: Failed to read response - {}", url)),
+    }
+}
+
+fn read_chunked<R: BufRead>(reader: &mut R, url: &str) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader, url)?;
+        let size = line.split(';').next().unwrap_or("").trim();
+        let size = usize::from_str_radix(size, 16)
+            .map_err(|_| err!("Request::send: Invalid chunk size {} - {}", size, url))?;
+        if size == 0 {
+            let mut trailer = 0;
+            loop {
+                let line = read_line(reader, url)?;
+                trailer += line.len();
+                if line.trim_end().is_empty() {
+                    return Ok(body);
+                }
+                if trailer > MAX_RESPONSE {
+                    return Err(err!("Request::send: Trailer exceeds {} bytes - {}", MAX_RESPONSE, url));
+                }
+            }
+        }
+        let start = body.len();
+        let end = start
+            .checked_add(size)
+            .filter(|end| *end <= MAX_RESPONSE)
+            .ok_or_else(|| err!("Request::send: Response body exceeds {} bytes - {}", MAX_RESPONSE, url))?;
+        body.resize(end, 0);
+        let mut crlf = [0u8; 2];
+        reader
+            .read_exact(&mut body[start..])
+            .and_then(|_| reader.read_exact(&mut crlf))
+            .map_err(|e| err_io!(e, "Request::send: Failed to read chunk - {}", url))?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Method;
+    use crate::error::PinMismatch;
+    use openssl::asn1::Asn1Time;
+    use openssl::hash::MessageDigest;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use openssl::sha::sha256;
+    use openssl::ssl::{SslAcceptor, SslMethod};
+    use openssl::x509::extension::SubjectAlternativeName;
+    use openssl::x509::{X509Name, X509};
+    use std::error::Error as StdError;
+    use std::fs;
+    use std::io::Cursor;
+    use std::net::TcpListener;
+    use std::process;
+    use std::thread;
+
+    /// Serves one HTTPS response with a self-signed certificate for localhost.
+    fn serve_tls(cert: &X509, key: &PKey<openssl::pkey::Private>, body: &'static str) -> u16 {
+        let mut acceptor = SslAcceptor::mozilla_intermediate(SslMethod::tls()).unwrap();
+        acceptor.set_certificate(cert).unwrap();
+        acceptor.set_private_key(key).unwrap();
+        let acceptor = acceptor.build();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            if let Ok(mut stream) = acceptor.accept(stream) {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf);
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        port
+    }
+
+    fn self_signed() -> (X509, PKey<openssl::pkey::Private>) {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+        let mut name = X509Name::builder().unwrap();
+        name.append_entry_by_text("CN", "localhost").unwrap();
+        let name = name.build();
+        let mut builder = X509::builder().unwrap();
+        builder.set_version(2).unwrap();
+        builder.set_subject_name(&name).unwrap();
+        builder.set_issuer_name(&name).unwrap();
+        builder.set_pubkey(&key).unwrap();
+        builder.set_not_before(&Asn1Time::days_from_now(0).unwrap()).unwrap();
+        builder.set_not_after(&Asn1Time::days_from_now(1).unwrap()).unwrap();
+        let san = SubjectAlternativeName::new().dns("localhost").build(&builder.x509v3_context(None, None)).unwrap();
+        builder.append_extension(san).unwrap();
+        builder.sign(&key, MessageDigest::sha256()).unwrap();
+        (builder.build(), key)
+    }
+
+    fn send(port: u16, tls: serde_json::Value) -> Result<String> {
+        let method: Method = serde_json::from_value(json!({
+            "type": "plain",
+            "url": format!("https://localhost:{}/ip", port),
+            "proxy": {"type": "none"},
+            "tls": tls
+        }))
+        .unwrap();
+        let http = method.http().unwrap();
+        let transport = Transport {
+            ip_version: &IpVersion::IPv4,
+            proxy: http.proxy(),
+            tls: http.tls(),
+            bind_address: None,
+            interface: None,
+        };
+        assert!(is_required(&transport));
+        Request::new(method.url(), http, &transport)?.send()
+    }
+
+    #[test]
+    fn test_send() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-tls-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (cert, key) = self_signed();
+        let ca_file = dir.join("ca.pem");
+        fs::write(&ca_file, cert.to_pem().unwrap()).unwrap();
+        let spki = cert.public_key().unwrap().public_key_to_der().unwrap();
+        let pin = format!("sha256/{}", base64::encode_block(&sha256(&spki)));
+
+        let port = serve_tls(&cert, &key, "203.0.113.7");
+        let body = send(port, json!({"ca_file": ca_file, "system_roots": false, "pins": [pin]})).unwrap();
+        assert_eq!(body, "203.0.113.7");
+
+        let port = serve_tls(&cert, &key, "203.0.113.7");
+        let e = send(port, json!({"ca_file": ca_file, "pins": ["sha256/AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="]})).unwrap_err();
+        assert!(e.source().unwrap().downcast_ref::<PinMismatch>().is_some());
+
+        // A root trusted by the system store is refused once system_roots is disabled.
+        std::env::set_var("SSL_CERT_FILE", &ca_file);
+        let port = serve_tls(&cert, &key, "203.0.113.7");
+        assert_eq!(send(port, json!({"pins": [pin]})).unwrap(), "203.0.113.7");
+        let port = serve_tls(&cert, &key, "203.0.113.7");
+        assert!(send(port, json!({"system_roots": false, "pins": [pin]})).is_err());
+        std::env::remove_var("SSL_CERT_FILE");
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_send_interface() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/ip", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 1024];
+            let _ = stream.read(&mut buf);
+            let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\n203.0.113.7");
+        });
+        let method: Method = serde_json::from_value(json!({"type": "plain", "url": url, "proxy": {"type": "none"}})).unwrap();
+        let transport = Transport {
+            ip_version: &IpVersion::IPv4,
+            proxy: method.http().unwrap().proxy(),
+            tls: None,
+            bind_address: None,
+            interface: Some("lo"),
+        };
+        assert!(is_required(&transport));
+        let request = Request::new(method.url(), method.http().unwrap(), &transport).unwrap();
+        assert_eq!(request.send().unwrap(), "203.0.113.7");
+    }
+
+    #[test]
+    fn test_read_chunked() {
+        let url = "http://192.0.2.254/";
+        let mut reader = Cursor::new(&b"4\r\n192.\r\n7\r\n0.2.1\r\n\r\n0\r\n\r\n"[..]);
+        assert_eq!(read_chunked(&mut reader, url).unwrap(), b"192.0.2.1\r\n");
+        for size in &["ffffffffffffffff", "7fffffff", "10001"] {
+            let chunk = format!("{}\r\n192.0.2.1\r\n0\r\n\r\n", size);
+            assert!(read_chunked(&mut Cursor::new(chunk.as_bytes()), url).is_err());
+        }
+        let chunks = format!("8000\r\n{0}\r\n8000\r\n{0}\r\n1\r\n0\r\n0\r\n\r\n", "0".repeat(0x8000));
+        assert!(read_chunked(&mut Cursor::new(chunks.as_bytes()), url).is_err());
+        let line = "1".repeat(MAX_RESPONSE + 1);
+        assert!(read_chunked(&mut Cursor::new(line.as_bytes()), url).is_err());
+    }
+}
@@ -0,0 +1,71 @@
+//! Determines the global IP address of the host by asking several providers
+//! and voting on their answers.
+//!
+//! [`Resolver`] runs the methods of a [`Profile`] from a [`Config`] and
+//! returns a [`Resolution`], either blocking or as a future that can be
+//! cancelled. [`Scoreboard`] and [`QueryLog`] keep the scores and query
+//! times the resolver weighs and throttles the methods with. The
+//! `globalip-memo` binary adds output files, hooks, state, the run lock and
+//! metrics on top of it.
+
+#[macro_use]
+extern crate log;
+extern crate env_logger;
+extern crate serde;
+#[cfg(test)]
+#[macro_use]
+extern crate serde_json;
+#[cfg(not(test))]
+extern crate serde_json;
+extern crate reqwest;
+extern crate openssl;
+extern crate socket2;
+extern crate libc;
+extern crate chrono;
+extern crate futures;
+extern crate scraper;
+extern crate roxmltree;
+#[cfg(test)]
+extern crate tokio;
+
+#[macro_use]
+mod error;
+#[macro_use]
+mod logging;
+mod syslog;
+mod env;
+mod util;
+mod net;
+mod tls;
+mod http;
+mod state;
+mod exec;
+mod ratelimit;
+mod score;
+mod provider;
+mod resolver;
+mod future;
+
+pub use crate::env::{Config, IpVersion, Method, Profile, SplitVote};
+pub use crate::error::{Error, ErrorKind, ErrorSource, Result};
+pub use crate::future::{CancelHandle, ResolveFuture};
+pub use crate::provider::{register_provider, Provider};
+pub use crate::ratelimit::QueryLog;
+pub use crate::resolver::{Resolution, Resolver};
+pub use crate::score::{MethodStats, Scoreboard};
+pub use crate::state::{Candidate, Consensus, MethodRecord};
+
+/// Internals shared with the `globalip-memo` binary: the environment, the
+/// logger and the file helpers. They are not part of the library API and
+/// may change in any release.
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::env::{Env, FileOptions, Hook, HookEvent, TemplateOutput};
+    pub use crate::exec::run_group;
+    pub use crate::state::State;
+    pub use crate::util::write_atomic;
+
+    pub mod logging {
+        pub use crate::logging::{init_logger, with_addr, with_error, with_url, FieldGuard};
+    }
+}
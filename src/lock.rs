@@ -0,0 +1,137 @@
+use globalip_memo::{ErrorKind, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOCK_FILENAME: &str = "globalip-memo.lock";
+const RETRY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Exclusive `flock` on a lock file that is never removed, so the kernel
+/// releases it when the holder exits and no stale lock has to be reclaimed.
+/// The file holds the pid of the holder for diagnostics only.
+#[derive(Debug)]
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+}
+
+fn read_pid(path: &Path) -> Option<i32> {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|s| s.trim().parse::<i32>().ok())
+        .filter(|pid| *pid > 0)
+}
+
+fn try_lock(file: &File) -> io::Result<bool> {
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+        return Ok(true);
+    }
+    let e = io::Error::last_os_error();
+    if e.raw_os_error() == Some(libc::EWOULDBLOCK) {
+        Ok(false)
+    } else {
+        Err(e)
+    }
+}
+
+impl RunLock {
+    pub fn acquire(dir: &Path, timeout: Duration) -> Result<RunLock> {
+        let path = dir.join(LOCK_FILENAME);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(|e| err_io!(e, "RunLock::acquire: Failed to open lock file - {}", path.display()))?;
+        let started = Instant::now();
+        loop {
+            let locked = try_lock(&file)
+                .map_err(|e| err_io!(e, "RunLock::acquire: Failed to lock {}", path.display()))?;
+            if locked {
+                break;
+            }
+            let pid = read_pid(&path).map(|pid| pid.to_string()).unwrap_or_else(|| "unknown".to_owned());
+            if started.elapsed() >= timeout {
+                return Err(err!(
+                    "RunLock::acquire: Another instance is running (pid {}) - {}",
+                    pid,
+                    path.display()
+                )
+                .with_kind(ErrorKind::Locked));
+            }
+            debug!("RunLock::acquire: Waiting for pid {} - {}", pid, path.display());
+            thread::sleep(RETRY_INTERVAL);
+        }
+        file.set_len(0)
+            .and_then(|_| (&file).write_all(process::id().to_string().as_bytes()))
+            .map_err(|e| err_io!(e, "RunLock::acquire: Failed to write pid - {}", path.display()))?;
+        debug!("RunLock::acquire: Lock acquired - {}", path.display());
+        Ok(RunLock { file, path })
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        match self.file.set_len(0) {
+            Ok(()) => debug!("RunLock::drop: Lock released - {}", self.path.display()),
+            Err(e) => warn!("RunLock::drop: Failed to clear lock file {} - {}", self.path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Barrier};
+
+    #[test]
+    fn test_run_lock() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-lock-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(LOCK_FILENAME);
+        {
+            let lock = RunLock::acquire(&dir, Duration::from_secs(0));
+            assert!(lock.is_ok());
+            assert_eq!(read_pid(&path), Some(process::id() as i32));
+            let res = RunLock::acquire(&dir, Duration::from_millis(300));
+            assert_eq!(res.unwrap_err().kind(), ErrorKind::Locked);
+        }
+        assert_eq!(read_pid(&path), None);
+
+        fs::write(&path, "999999999").unwrap();
+        let lock = RunLock::acquire(&dir, Duration::from_secs(0));
+        assert!(lock.is_ok());
+        assert_eq!(read_pid(&path), Some(process::id() as i32));
+        drop(lock);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_lock_race() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-race-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for _ in 0..20 {
+            let barrier = Arc::new(Barrier::new(2));
+            let acquirers: Vec<_> = (0..2)
+                .map(|_| {
+                    let (dir, barrier) = (dir.clone(), barrier.clone());
+                    thread::spawn(move || {
+                        barrier.wait();
+                        let lock = RunLock::acquire(&dir, Duration::from_secs(0));
+                        // Hold the lock until both have tried.
+                        barrier.wait();
+                        lock.is_ok()
+                    })
+                })
+                .collect();
+            let acquired = acquirers.into_iter().map(|t| t.join().unwrap()).filter(|ok| *ok).count();
+            assert_eq!(acquired, 1);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
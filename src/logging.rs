@@ -1,33 +1,415 @@
-use env_logger::fmt::{Formatter, Target};
-use env_logger::{Builder, Env};
-use log::Record;
-use std::io::Write;
+//! Logger of the binary, adding the url, address and error chain in scope
+//! to each record, and the guards that set them.
 
-const FILTER_ENV: &'static str = "GLOBALIP_MEMO_LOG";
-const WRITE_STYLE_ENV: &'static str = "GLOBALIP_MEMO_LOG_STYLE";
+use crate::syslog::Syslog;
+use chrono::{SecondsFormat, Utc};
+use env_logger::filter::{Builder, Filter};
+use log::{Level, Log, Metadata, Record};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 
-const DEFAULT_FILTER: &'static str = "warn";
-const DEFAULT_WRITE_STYLE: &'static str = "auto";
+const FILTER_ENV: &str = "GLOBALIP_MEMO_LOG";
+const FORMAT_ENV: &str = "GLOBALIP_MEMO_LOG_FORMAT";
+const STYLE_ENV: &str = "GLOBALIP_MEMO_LOG_STYLE";
+const FILE_ENV: &str = "GLOBALIP_MEMO_LOG_FILE";
+const MAX_SIZE_ENV: &str = "GLOBALIP_MEMO_LOG_MAX_SIZE";
+const ROTATE_ENV: &str = "GLOBALIP_MEMO_LOG_ROTATE";
+const SYSLOG_ENV: &str = "GLOBALIP_MEMO_LOG_SYSLOG";
+const FACILITY_ENV: &str = "GLOBALIP_MEMO_LOG_FACILITY";
+const APP_NAME_ENV: &str = "GLOBALIP_MEMO_LOG_APP_NAME";
 
-pub fn init_logger() {
-    let env = Env::default()
-        .filter_or(FILTER_ENV, DEFAULT_FILTER)
-        .write_style_or(WRITE_STYLE_ENV, DEFAULT_WRITE_STYLE);
-
-    Builder::from_env(env)
-        .format(|buf: &mut Formatter, record: &Record| {
-            let ts = buf.timestamp();
-            writeln!(
-                buf,
-                "{} [{}] {}:{} {} - {}",
-                ts,
-                record.level(),
+const DEFAULT_FILTER: &str = "warn";
+const DEFAULT_STYLE: &str = "auto";
+const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_ROTATE: usize = 5;
+const DEFAULT_FACILITY: &str = "user";
+const DEFAULT_APP_NAME: &str = "globalip-memo";
+
+#[derive(Debug, Clone, Default)]
+struct Fields {
+    url: Option<String>,
+    addr: Option<String>,
+    error_chain: Vec<String>,
+}
+
+thread_local! {
+    static FIELDS: RefCell<Fields> = RefCell::new(Fields::default());
+}
+
+/// Restores the previous log fields when dropped.
+#[must_use]
+pub struct FieldGuard {
+    previous: Option<Fields>,
+}
+
+impl Drop for FieldGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            FIELDS.with(|fields| *fields.borrow_mut() = previous);
+        }
+    }
+}
+
+fn push_fields<F: FnOnce(&mut Fields)>(f: F) -> FieldGuard {
+    FIELDS.with(|fields| {
+        let mut fields = fields.borrow_mut();
+        let previous = fields.clone();
+        f(&mut fields);
+        FieldGuard {
+            previous: Some(previous),
+        }
+    })
+}
+
+pub fn with_url(url: &str) -> FieldGuard {
+    push_fields(|fields| fields.url = Some(url.to_owned()))
+}
+
+pub fn with_addr(addr: &IpAddr) -> FieldGuard {
+    push_fields(|fields| fields.addr = Some(format!("{}", addr)))
+}
+
+pub fn with_error(e: &(dyn std::error::Error + 'static)) -> FieldGuard {
+    push_fields(|fields| {
+        fields.error_chain.clear();
+        let mut source = Some(e);
+        while let Some(err) = source {
+            fields.error_chain.push(format!("{}", err));
+            source = err.source();
+        }
+    })
+}
+
+/// Logs a message together with the chain of sources of an error.
+macro_rules! log_error {
+    ($lvl:expr, $e:expr, $($arg:tt)+) => {{
+        let _fields = $crate::logging::with_error($e);
+        log!($lvl, $($arg)+);
+    }};
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    timestamp: &'a str,
+    level: &'a str,
+    target: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    addr: Option<&'a str>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    error_chain: &'a [String],
+}
+
+/// Formats a record into messages without the timestamp and level header.
+fn format_record(format: Format, record: &Record, fields: &Fields) -> Vec<String> {
+    match format {
+        Format::Text => {
+            let header = format!(
+                "{}:{} {} - ",
                 record.file().unwrap_or("unknown"),
                 record.line().unwrap_or(0),
                 record.target(),
-                record.args(),
-            )
-        })
-        .target(Target::Stderr)
-        .init();
+            );
+            let mut messages = vec![format!("{}{}", header, record.args())];
+            for (i, err) in fields.error_chain.iter().enumerate() {
+                let label = if i == 0 { "error" } else { "error source" };
+                messages.push(format!("{}{} - {}", header, label, err));
+            }
+            messages
+        }
+        Format::Json => {
+            let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+            let level = format!("{}", record.level()).to_lowercase();
+            let json = JsonRecord {
+                timestamp: &ts,
+                level: &level,
+                target: record.target(),
+                file: record.file(),
+                line: record.line(),
+                message: format!("{}", record.args()),
+                url: fields.url.as_deref(),
+                addr: fields.addr.as_deref(),
+                error_chain: &fields.error_chain,
+            };
+            serde_json::to_string(&json).into_iter().collect()
+        }
+    }
+}
+
+/// The level in the ANSI colour env_logger uses for it.
+fn styled_level(level: Level) -> String {
+    let color = match level {
+        Level::Error => 31,
+        Level::Warn => 33,
+        Level::Info => 32,
+        Level::Debug => 34,
+        Level::Trace => 36,
+    };
+    format!("\x1b[{}m{}\x1b[0m", color, level)
+}
+
+fn format_lines(format: Format, level: Level, messages: &[String], color: bool) -> Vec<u8> {
+    let ts = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    let level = if color { styled_level(level) } else { level.to_string() };
+    let mut buf = Vec::new();
+    for message in messages {
+        let _ = match format {
+            Format::Text => writeln!(buf, "{} [{}] {}", ts, level, message),
+            Format::Json => writeln!(buf, "{}", message),
+        };
+    }
+    buf
+}
+
+#[derive(Debug)]
+struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    rotate: usize,
+    file: Option<File>,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn new(path: &Path, max_size: u64, rotate: usize) -> Self {
+        RotatingFile {
+            path: path.to_owned(),
+            max_size,
+            rotate,
+            file: None,
+            size: 0,
+        }
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        if self.rotate == 0 {
+            return fs::remove_file(&self.path);
+        }
+        for index in (1..self.rotate).rev() {
+            let from = self.rotated(index);
+            if from.exists() {
+                fs::rename(&from, self.rotated(index + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.file.is_some() && self.size > 0 && self.size + data.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+            if self.size > 0 && self.size + data.len() as u64 > self.max_size {
+                self.rotate()?;
+                return self.write(data);
+            }
+        }
+        let file = self.file.as_mut().unwrap();
+        file.write_all(data)?;
+        self.size += data.len() as u64;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+enum Sink {
+    /// Standard error, with coloured levels in the text format when `color` is set.
+    Stderr { color: bool },
+    File(RotatingFile),
+    Syslog(Syslog),
+}
+
+impl Sink {
+    fn write(&mut self, format: Format, level: Level, messages: &[String]) {
+        let result = match self {
+            Sink::Stderr { color } => io::stderr().write_all(&format_lines(format, level, messages, *color)),
+            Sink::File(file) => file.write(&format_lines(format, level, messages, false)),
+            Sink::Syslog(syslog) => messages.iter().try_for_each(|message| syslog.send(level, message)),
+        };
+        if let Err(e) = result {
+            let _ = writeln!(io::stderr(), "globalip-memo: Failed to write log - {}", e);
+            let _ = io::stderr().write_all(&format_lines(format, level, messages, false));
+        }
+    }
+}
+
+struct Logger {
+    filter: Filter,
+    format: Format,
+    sink: Mutex<Sink>,
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.filter.matches(record) {
+            return;
+        }
+        let messages = FIELDS.with(|fields| format_record(self.format, record, &fields.borrow()));
+        self.sink
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .write(self.format, record.level(), &messages);
+    }
+
+    fn flush(&self) {
+        if let Sink::File(RotatingFile { file: Some(file), .. }) =
+            &mut *self.sink.lock().unwrap_or_else(|e| e.into_inner())
+        {
+            let _ = file.flush();
+        }
+    }
+}
+
+fn env_or<T: std::str::FromStr>(name: &str, default: T) -> T {
+    match std::env::var(name) {
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            eprintln!("globalip-memo: Invalid value for {} - {}", name, value);
+            default
+        }),
+        Err(_) => default,
+    }
+}
+
+pub fn init_logger() {
+    let spec = std::env::var(FILTER_ENV).unwrap_or_else(|_| DEFAULT_FILTER.to_owned());
+    let filter = Builder::new().parse(&spec).build();
+
+    let format = match std::env::var(FORMAT_ENV).as_ref().map(String::as_str) {
+        Ok("json") => Format::Json,
+        Ok("text") | Err(_) => Format::Text,
+        Ok(other) => {
+            eprintln!("globalip-memo: Unknown log format - {}", other);
+            Format::Text
+        }
+    };
+
+    let style = std::env::var(STYLE_ENV).unwrap_or_else(|_| DEFAULT_STYLE.to_owned());
+    let color = format == Format::Text
+        && match style.as_str() {
+            "always" => true,
+            "never" => false,
+            other => {
+                if other != "auto" {
+                    eprintln!("globalip-memo: Unknown log style - {}", other);
+                }
+                unsafe { libc::isatty(libc::STDERR_FILENO) == 1 }
+            }
+        };
+
+    let sink = match (std::env::var(SYSLOG_ENV), std::env::var_os(FILE_ENV)) {
+        (Ok(target), _) => {
+            let facility = std::env::var(FACILITY_ENV).unwrap_or_else(|_| DEFAULT_FACILITY.to_owned());
+            let app_name = std::env::var(APP_NAME_ENV).unwrap_or_else(|_| DEFAULT_APP_NAME.to_owned());
+            match Syslog::new(&target, &facility, &app_name) {
+                Ok(syslog) => Sink::Syslog(syslog),
+                Err(e) => {
+                    eprintln!("globalip-memo: Failed to set up syslog - {}", e);
+                    Sink::Stderr { color }
+                }
+            }
+        }
+        (Err(_), Some(path)) => Sink::File(RotatingFile::new(
+            Path::new(&path),
+            env_or(MAX_SIZE_ENV, DEFAULT_MAX_SIZE),
+            env_or(ROTATE_ENV, DEFAULT_ROTATE),
+        )),
+        (Err(_), None) => Sink::Stderr { color },
+    };
+
+    let max_level = filter.filter();
+    let logger = Logger {
+        filter,
+        format,
+        sink: Mutex::new(sink),
+    };
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn test_rotating_file() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-log-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("globalip-memo.log");
+        let mut file = RotatingFile::new(&path, 10, 2);
+        for line in &["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        assert_eq!(fs::read_to_string(file.rotated(1)).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(file.rotated(2)).unwrap(), "second\n");
+        assert!(!file.rotated(3).exists());
+
+        let mut reopened = RotatingFile::new(&path, 10, 2);
+        reopened.write(b"fifth\n").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fifth\n");
+        assert_eq!(fs::read_to_string(reopened.rotated(1)).unwrap(), "fourth\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_format_record() {
+        let _url = with_url("https://example.com/");
+        let err = err!("outer");
+        let _error = with_error(&err);
+        let fields = FIELDS.with(|fields| fields.borrow().clone());
+        let record = Record::builder()
+            .args(format_args!("failed"))
+            .level(log::Level::Warn)
+            .target("globalip_memo::proc")
+            .build();
+        let messages = format_record(Format::Json, &record, &fields);
+        assert_eq!(messages.len(), 1);
+        let value: serde_json::Value = serde_json::from_str(&messages[0]).unwrap();
+        assert_eq!(value["level"], "warn");
+        assert_eq!(value["message"], "failed");
+        assert_eq!(value["url"], "https://example.com/");
+        assert_eq!(value["error_chain"][0], "outer");
+        assert!(value.get("addr").is_none());
+
+        let messages = format_record(Format::Text, &record, &fields);
+        assert_eq!(messages.len(), 2);
+        assert!(messages[1].ends_with("error - outer"));
+        let text = String::from_utf8(format_lines(Format::Text, log::Level::Warn, &messages, false)).unwrap();
+        assert!(text.lines().nth(1).unwrap().contains("[WARN] unknown:0 globalip_memo::proc - error - outer"));
+        let text = String::from_utf8(format_lines(Format::Text, log::Level::Warn, &messages, true)).unwrap();
+        assert!(text.lines().nth(1).unwrap().contains("[\x1b[33mWARN\x1b[0m] unknown:0"));
+    }
 }
@@ -1,48 +1,192 @@
 #[macro_use]
 extern crate log;
-extern crate env_logger;
-extern crate serde;
-#[cfg(test)]
-#[macro_use]
-extern crate serde_json;
-#[cfg(not(test))]
+extern crate globalip_memo;
+extern crate chrono;
+extern crate libc;
 extern crate serde_json;
-extern crate reqwest;
 
-mod logging;
-#[macro_use]
-mod error;
-mod env;
-mod util;
+macro_rules! err {
+    ($msg:expr) => {
+        globalip_memo::Error::new($msg, globalip_memo::ErrorSource::None)
+    };
+    ($f:expr, $($arg:expr),+) => {
+        err!(format!($f, $($arg,)+))
+    };
+}
+
+macro_rules! err_io {
+    ($e:expr, $msg:expr) => {
+        globalip_memo::Error::new($msg, globalip_memo::ErrorSource::Io($e))
+    };
+    ($e:expr, $f:expr, $($arg:expr),+) => {
+        err_io!($e, format!($f, $($arg,)+))
+    };
+}
+
+macro_rules! err_json {
+    ($e:expr, $msg:expr) => {
+        globalip_memo::Error::new($msg, globalip_memo::ErrorSource::Json($e))
+    };
+    ($e:expr, $f:expr, $($arg:expr),+) => {
+        err_json!($e, format!($f, $($arg,)+))
+    };
+}
+
+/// Logs a message together with the chain of sources of an error.
+macro_rules! log_error {
+    ($lvl:expr, $e:expr, $($arg:tt)+) => {{
+        let _fields = globalip_memo::__private::logging::with_error($e);
+        log!($lvl, $($arg)+);
+    }};
+}
+
+mod cli;
+mod hook;
+mod lock;
+mod metrics;
 mod proc;
 
+use globalip_memo::__private::{logging, Env};
+use globalip_memo::{ErrorKind, Profile, Result, Scoreboard};
+use lock::RunLock;
+use metrics::Registry;
+use proc::Outcome;
+use std::sync::Arc;
+use std::thread;
+
+const EXIT_UNCHANGED: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_UPDATED: i32 = 10;
+const EXIT_FETCH: i32 = 20;
+const EXIT_CONSENSUS: i32 = 21;
+const EXIT_CONFIG: i32 = 30;
+const EXIT_OUTPUT: i32 = 40;
+const EXIT_LOCKED: i32 = 75;
+
+fn exit_code(kind: ErrorKind) -> i32 {
+    match kind {
+        ErrorKind::Other | ErrorKind::Cancelled => EXIT_FAILURE,
+        ErrorKind::Throttled => EXIT_UNCHANGED,
+        ErrorKind::Config => EXIT_CONFIG,
+        ErrorKind::Fetch => EXIT_FETCH,
+        ErrorKind::Consensus => EXIT_CONSENSUS,
+        ErrorKind::Output => EXIT_OUTPUT,
+        ErrorKind::Locked => EXIT_LOCKED,
+    }
+}
+
 fn main() {
     logging::init_logger();
 
-    match run() {
-        Ok(..) => info!("globalip-memo: successfully completed"),
+    let code = match run() {
+        Ok(code) => {
+            info!("globalip-memo: successfully completed");
+            code
+        }
+        Err(ref e) if e.kind() == ErrorKind::Locked => {
+            warn!("globalip-memo: {}", e);
+            exit_code(e.kind())
+        }
         Err(ref e) => {
-            error!("globalip-memo: error - {}", e);
-            let mut source = std::error::Error::source(e);
-            loop {
-                match source {
-                    Some(err) => {
-                        error!("globalip-memo: error source - {}", err);
-                        source = err.source();
-                    }
-                    None => break,
+            log_error!(log::Level::Error, e, "globalip-memo: failed");
+            exit_code(e.kind())
+        }
+    };
+    std::process::exit(code);
+}
+
+fn run() -> Result<i32> {
+    info!("globalip-memo: start processing");
+    let args = cli::Args::parse(std::env::args().skip(1))?;
+    let env = Env::new()?;
+    let profiles = env.select_profiles(args.profiles())?;
+    if args.scoreboard() {
+        print_scoreboard(&env, &profiles).map_err(|e| e.with_kind(ErrorKind::Config))?;
+        return Ok(EXIT_UNCHANGED);
+    }
+    let registry = Arc::new(Registry::default());
+    if !args.daemon() {
+        return run_once(&env, &profiles, &registry);
+    }
+    if let Some(listen) = env.config().metrics().listen() {
+        metrics::serve(listen, registry.clone()).map_err(|e| e.with_kind(ErrorKind::Config))?;
+    }
+    info!("globalip-memo: daemon mode, interval {:?}", env.config().interval());
+    loop {
+        match run_once(&env, &profiles, &registry) {
+            Ok(_) => info!("globalip-memo: round completed"),
+            Err(ref e) if e.kind() == ErrorKind::Locked => {
+                warn!("globalip-memo: round skipped - {}", e);
+            }
+            Err(ref e) => {
+                log_error!(log::Level::Error, e, "globalip-memo: round failed");
+            }
+        }
+        thread::sleep(env.config().interval());
+    }
+}
+
+fn run_once(env: &Env, profiles: &[&Profile], registry: &Registry) -> Result<i32> {
+    let _lock = RunLock::acquire(env.dir(), env.config().lock_timeout())?;
+    let mut failed = Vec::new();
+    let mut updated = false;
+    for profile in profiles {
+        match proc::run(env, profile, registry) {
+            Ok(outcome) => {
+                if let Outcome::Updated(..) = outcome {
+                    updated = true;
                 }
+                println!("{}: {}", profile.name(), outcome);
+            }
+            Err(ref e) => {
+                log_error!(log::Level::Error, e, "globalip-memo: profile {} failed", profile.name());
+                println!("{}: failed - {}", profile.name(), e);
+                failed.push(e.kind());
             }
         }
     }
+    let metrics = env.config().metrics();
+    if let Some(textfile) = metrics.textfile() {
+        metrics::write_textfile(&env.dir().join(textfile), registry, metrics.textfile_options())
+            .map_err(|e| e.with_kind(ErrorKind::Output))?;
+    }
+    match failed.first() {
+        Some(kind) => Err(err!("{} of {} profiles failed", failed.len(), profiles.len()).with_kind(*kind)),
+        None if updated => Ok(EXIT_UPDATED),
+        None => Ok(EXIT_UNCHANGED),
+    }
 }
 
-fn run() -> error::Result<()> {
-    info!("globalip-memo: start processing");
-    let env = env::Env::new()?;
-    let list = proc::fetch(&env);
-    let addr = proc::process_fetch_result(list)?;
-    let old_addr = proc::find_old_addr(&env);
-    proc::output(&env, &addr, &old_addr.as_ref())?;
+fn print_scoreboard(env: &Env, profiles: &[&Profile]) -> Result<()> {
+    let options = env
+        .config()
+        .scoring()
+        .ok_or_else(|| err!("print_scoreboard: scoring is not configured"))?;
+    let board = Scoreboard::load(&env.dir().join(options.file()));
+    println!(
+        "{:<12} {:>7} {:>8} {:>9} {:>10} {:>7} {:>9}  url",
+        "profile", "samples", "success", "agreement", "latency_ms", "weight", "effective"
+    );
+    for profile in profiles {
+        for method in profile.methods() {
+            let stats = board.stats(profile, method).cloned().unwrap_or_default();
+            let effective = if options.adaptive() {
+                method.weight() * stats.multiplier(options)
+            } else {
+                method.weight()
+            };
+            println!(
+                "{:<12} {:>7} {:>7.1}% {:>8.1}% {:>10.0} {:>7.2} {:>9.2}  {}",
+                profile.name(),
+                stats.samples,
+                stats.success_rate * 100f64,
+                stats.agreement_rate * 100f64,
+                stats.latency_ms,
+                method.weight(),
+                effective,
+                method.url()
+            );
+        }
+    }
     Ok(())
 }
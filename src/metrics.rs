@@ -0,0 +1,259 @@
+use chrono::{DateTime, Utc};
+use globalip_memo::__private::{write_atomic, FileOptions};
+use globalip_memo::{Consensus, MethodRecord, Profile, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as FmtWrite;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const PREFIX: &str = "globalip_memo";
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+#[derive(Debug, Clone)]
+struct MethodMetrics {
+    url: String,
+    success: bool,
+    latency: Duration,
+}
+
+#[derive(Debug, Clone, Default)]
+struct ProfileMetrics {
+    methods: Vec<MethodMetrics>,
+    agreement_ratio: Option<f64>,
+    distinct_addresses: usize,
+    last_change: Option<DateTime<Utc>>,
+    last_success: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Default)]
+pub struct Registry {
+    profiles: Mutex<BTreeMap<String, ProfileMetrics>>,
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn timestamp(t: &DateTime<Utc>) -> f64 {
+    t.timestamp_millis() as f64 / 1000f64
+}
+
+impl Registry {
+    fn with_profile<F>(&self, profile: &Profile, f: F)
+    where
+        F: FnOnce(&mut ProfileMetrics),
+    {
+        let mut profiles = self.profiles.lock().unwrap_or_else(|e| e.into_inner());
+        f(profiles.entry(profile.name().to_owned()).or_default());
+    }
+
+    pub fn record_fetch(&self, profile: &Profile, records: &[MethodRecord]) {
+        self.with_profile(profile, |metrics| {
+            metrics.methods = records
+                .iter()
+                .map(|r| MethodMetrics {
+                    url: r.url.clone(),
+                    success: r.addr.is_some(),
+                    latency: Duration::from_millis(r.latency_ms),
+                })
+                .collect();
+            metrics.agreement_ratio = None;
+            metrics.distinct_addresses = 0;
+        });
+    }
+
+    pub fn record_consensus(&self, profile: &Profile, consensus: &Consensus) {
+        self.with_profile(profile, |metrics| {
+            metrics.agreement_ratio = if consensus.total_weight > 0f64 {
+                Some(consensus.weight / consensus.total_weight)
+            } else {
+                None
+            };
+            metrics.distinct_addresses = consensus.candidates.len();
+        });
+    }
+
+    pub fn record_success(&self, profile: &Profile, last_change: Option<DateTime<Utc>>, now: DateTime<Utc>) {
+        self.with_profile(profile, |metrics| {
+            if last_change.is_some() {
+                metrics.last_change = last_change;
+            }
+            metrics.last_success = Some(now);
+        });
+    }
+
+    pub fn last_change(&self, profile: &Profile) -> Option<DateTime<Utc>> {
+        let profiles = self.profiles.lock().unwrap_or_else(|e| e.into_inner());
+        profiles.get(profile.name()).and_then(|metrics| metrics.last_change)
+    }
+
+    pub fn render(&self, now: &DateTime<Utc>) -> String {
+        let profiles = self.profiles.lock().unwrap_or_else(|e| e.into_inner());
+        let mut out = String::new();
+        let mut family = |name: &str, help: &str, samples: Vec<(String, f64)>| {
+            if samples.is_empty() {
+                return;
+            }
+            let _ = writeln!(out, "# HELP {}_{} {}", PREFIX, name, help);
+            let _ = writeln!(out, "# TYPE {}_{} gauge", PREFIX, name);
+            for (labels, value) in samples {
+                let _ = writeln!(out, "{}_{}{{{}}} {}", PREFIX, name, labels, value);
+            }
+        };
+        let method_samples = |f: &dyn Fn(&MethodMetrics) -> f64| -> Vec<(String, f64)> {
+            profiles
+                .iter()
+                .flat_map(|(name, metrics)| {
+                    metrics.methods.iter().enumerate().map(move |(index, method)| {
+                        (
+                            format!(
+                                r#"profile="{}",index="{}",url="{}""#,
+                                escape(name),
+                                index,
+                                escape(&method.url)
+                            ),
+                            f(method),
+                        )
+                    })
+                })
+                .collect()
+        };
+        let profile_samples = |f: &dyn Fn(&ProfileMetrics) -> Option<f64>| -> Vec<(String, f64)> {
+            profiles
+                .iter()
+                .filter_map(|(name, metrics)| {
+                    f(metrics).map(|value| (format!(r#"profile="{}""#, escape(name)), value))
+                })
+                .collect()
+        };
+
+        family(
+            "method_success",
+            "Whether the method returned an address in the last run.",
+            method_samples(&|m| if m.success { 1f64 } else { 0f64 }),
+        );
+        family(
+            "method_latency_seconds",
+            "Time taken by the method in the last run.",
+            method_samples(&|m| m.latency.as_secs_f64()),
+        );
+        family(
+            "agreement_ratio",
+            "Weight of the chosen address divided by the weight of all responding methods.",
+            profile_samples(&|m| m.agreement_ratio),
+        );
+        family(
+            "distinct_addresses",
+            "Number of distinct addresses returned in the last run.",
+            profile_samples(&|m| m.agreement_ratio.map(|_| m.distinct_addresses as f64)),
+        );
+        family(
+            "seconds_since_change",
+            "Seconds since the address last changed.",
+            profile_samples(&|m| {
+                m.last_change
+                    .map(|t| (now.signed_duration_since(t).num_milliseconds() as f64 / 1000f64).max(0f64))
+            }),
+        );
+        family(
+            "last_success_timestamp_seconds",
+            "Unix time of the last successful run.",
+            profile_samples(&|m| m.last_success.as_ref().map(timestamp)),
+        );
+        out
+    }
+}
+
+pub fn write_textfile(path: &Path, registry: &Registry, options: &FileOptions) -> Result<()> {
+    let text = registry.render(&Utc::now());
+    write_atomic(path, text.as_bytes(), options)?;
+    debug!("write_textfile: Metrics written - {}", path.display());
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, registry: &Registry) -> std::io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(10)))?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", registry.render(&Utc::now())),
+        (Some("GET"), Some(_)) => ("404 Not Found", "Not Found\n".to_owned()),
+        _ => ("405 Method Not Allowed", "Method Not Allowed\n".to_owned()),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    )?;
+    stream.flush()
+}
+
+pub fn serve(listen: &str, registry: Arc<Registry>) -> Result<()> {
+    let listener = TcpListener::bind(listen)
+        .map_err(|e| err_io!(e, "serve: Failed to listen on {}", listen))?;
+    info!("serve: Serving metrics on http://{}/metrics", listen);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = respond(stream, &registry) {
+                        debug!("serve: Failed to respond - {}", e);
+                    }
+                }
+                Err(e) => warn!("serve: Failed to accept connection - {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        let registry = Registry::default();
+        let now = Utc::now();
+        registry.profiles.lock().unwrap().insert(
+            "v4".to_owned(),
+            ProfileMetrics {
+                methods: vec![
+                    MethodMetrics {
+                        url: "https://example.com/\"ip\"".to_owned(),
+                        success: true,
+                        latency: Duration::from_millis(250),
+                    },
+                    MethodMetrics {
+                        url: "https://example.org/".to_owned(),
+                        success: false,
+                        latency: Duration::from_secs(2),
+                    },
+                ],
+                agreement_ratio: Some(0.5),
+                distinct_addresses: 2,
+                last_change: Some(now - chrono::Duration::seconds(90)),
+                last_success: Some(now),
+            },
+        );
+        let text = registry.render(&now);
+        assert!(text.contains("# TYPE globalip_memo_method_success gauge\n"));
+        assert!(text.contains(r#"globalip_memo_method_success{profile="v4",index="0",url="https://example.com/\"ip\""} 1"#));
+        assert!(text.contains(r#"globalip_memo_method_success{profile="v4",index="1",url="https://example.org/"} 0"#));
+        assert!(text.contains(r#"globalip_memo_method_latency_seconds{profile="v4",index="1",url="https://example.org/"} 2"#));
+        assert!(text.contains(r#"globalip_memo_agreement_ratio{profile="v4"} 0.5"#));
+        assert!(text.contains(r#"globalip_memo_distinct_addresses{profile="v4"} 2"#));
+        assert!(text.contains(r#"globalip_memo_seconds_since_change{profile="v4"} 90"#));
+    }
+}
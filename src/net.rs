@@ -0,0 +1,187 @@
+use crate::env::{IpVersion, Proxy, TlsOptions};
+use crate::error::Result;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::ffi::CStr;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::ptr;
+use std::time::Duration;
+
+#[derive(Debug)]
+pub struct Transport<'a> {
+    pub ip_version: &'a IpVersion,
+    pub proxy: Option<&'a Proxy>,
+    pub tls: Option<&'a TlsOptions>,
+    pub bind_address: Option<IpAddr>,
+    pub interface: Option<&'a str>,
+}
+
+impl<'a> Transport<'a> {
+    pub fn local_addr(&self) -> Result<Option<IpAddr>> {
+        match (self.bind_address, self.interface) {
+            (Some(addr), _) => {
+                if !matches_version(self.ip_version, &addr) {
+                    return Err(err!(
+                        "local_addr: bind address {} does not match {}",
+                        addr,
+                        self.ip_version
+                    ));
+                }
+                Ok(Some(addr))
+            }
+            (None, Some(name)) => interface_addr(name, self.ip_version).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+}
+
+pub fn matches_version(ip_version: &IpVersion, addr: &IpAddr) -> bool {
+    matches!(
+        (ip_version, addr),
+        (IpVersion::IPv4, IpAddr::V4(..)) | (IpVersion::IPv6, IpAddr::V6(..))
+    )
+}
+
+fn is_link_local(addr: &IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(v4) => v4.is_link_local(),
+        IpAddr::V6(v6) => (v6.segments()[0] & 0xffc0) == 0xfe80,
+    }
+}
+
+pub fn interface_addr(name: &str, ip_version: &IpVersion) -> Result<IpAddr> {
+    let mut ifap: *mut libc::ifaddrs = ptr::null_mut();
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return Err(err_io!(
+            io::Error::last_os_error(),
+            "interface_addr: Failed to list interfaces - {}",
+            name
+        ));
+    }
+    let mut found = None;
+    let mut cur = ifap;
+    while !cur.is_null() {
+        let ifa = unsafe { &*cur };
+        cur = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() {
+            continue;
+        }
+        let ifname = unsafe { CStr::from_ptr(ifa.ifa_name) };
+        if ifname.to_bytes() != name.as_bytes() {
+            continue;
+        }
+        let addr = match i32::from(unsafe { (*ifa.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                let sin = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in) };
+                IpAddr::V4(Ipv4Addr::from(u32::from_be(sin.sin_addr.s_addr)))
+            }
+            libc::AF_INET6 => {
+                let sin6 = unsafe { &*(ifa.ifa_addr as *const libc::sockaddr_in6) };
+                IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr))
+            }
+            _ => continue,
+        };
+        if matches_version(ip_version, &addr) && !is_link_local(&addr) {
+            found = Some(addr);
+            break;
+        }
+    }
+    unsafe { libc::freeifaddrs(ifap) };
+    match found {
+        Some(addr) => {
+            debug!("interface_addr: {} address {} found - {}", ip_version, addr, name);
+            Ok(addr)
+        }
+        None => Err(err!("interface_addr: {} address not found - {}", ip_version, name)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn bind_device(socket: &Socket, name: &str) -> Result<()> {
+    let device = std::ffi::CString::new(name)
+        .map_err(|_| err!("bind_device: Invalid interface name - {}", name))?;
+    socket
+        .bind_device(Some(&device))
+        .map_err(|e| err_io!(e, "bind_device: Failed to bind to {}", name))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_device(_socket: &Socket, name: &str) -> Result<()> {
+    Err(err!("bind_device: Binding to an interface requires Linux - {}", name))
+}
+
+pub fn connect_tcp(addr: &SocketAddr, transport: &Transport, timeout: Duration) -> Result<TcpStream> {
+    let domain = if addr.is_ipv4() {
+        Domain::ipv4()
+    } else {
+        Domain::ipv6()
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))
+        .map_err(|e| err_io!(e, "connect_tcp: Failed to create socket - {}", addr))?;
+    if let Some(name) = transport.interface {
+        bind_device(&socket, name)?;
+    }
+    if let Some(local) = transport.bind_address {
+        socket
+            .bind(&SockAddr::from(SocketAddr::new(local, 0)))
+            .map_err(|e| err_io!(e, "connect_tcp: Failed to bind {}", local))?;
+    }
+    socket
+        .connect_timeout(&SockAddr::from(*addr), timeout)
+        .map_err(|e| err_io!(e, "connect_tcp: Failed to connect {}", addr))?;
+    Ok(socket.into_tcp_stream())
+}
+
+/// Connects to the first reachable address of the host in the IP version, with read and write timeouts.
+pub fn connect(host: &str, port: u16, transport: &Transport, timeout: Duration) -> Result<TcpStream> {
+    let addrs: Vec<SocketAddr> = (host, port)
+        .to_socket_addrs()
+        .map_err(|e| err_io!(e, "connect: Failed to resolve {}", host))?
+        .filter(|addr| matches_version(transport.ip_version, &addr.ip()))
+        .collect();
+    let mut last_err = None;
+    for addr in &addrs {
+        match connect_tcp(addr, transport, timeout) {
+            Ok(stream) => {
+                stream
+                    .set_read_timeout(Some(timeout))
+                    .and_then(|_| stream.set_write_timeout(Some(timeout)))
+                    .map_err(|e| err_io!(e, "connect: Failed to set timeout - {}", addr))?;
+                return Ok(stream);
+            }
+            Err(e) => last_err = Some(e),
+        }
+    }
+    match last_err {
+        Some(e) => Err(e),
+        None => Err(err!("connect: {} address not found - {}", transport.ip_version, host)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local_addr() {
+        let transport = Transport {
+            ip_version: &IpVersion::IPv4,
+            proxy: None,
+            tls: None,
+            bind_address: None,
+            interface: Some("lo"),
+        };
+        let res = transport.local_addr();
+        assert!(res.is_ok());
+        assert_eq!(res.unwrap(), Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+
+        let transport = Transport {
+            ip_version: &IpVersion::IPv6,
+            proxy: None,
+            tls: None,
+            bind_address: Some(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            interface: None,
+        };
+        assert!(transport.local_addr().is_err());
+    }
+}
@@ -1,79 +1,45 @@
-use crate::env::{Config, Env, Method};
-use crate::error::Result;
-use crate::util;
-use std::cell::Cell;
-use std::fs::File;
-use std::io::{Read, Write};
-use std::net::IpAddr;
+use crate::hook;
+use crate::metrics::Registry;
+use chrono::{DateTime, Utc};
+use globalip_memo::__private::{logging, write_atomic, Env, HookEvent, State, TemplateOutput};
+use globalip_memo::{ErrorKind, Profile, QueryLog, Resolution, Resolver, Result, Scoreboard, SplitVote};
+use log::Level;
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Read;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 
 const OUTPUT_MAX_SIZE: usize = 39;
 
-fn get_globalip(method: &Method, config: &Config) -> Result<IpAddr> {
-    let mut body = util::get_body(method.url(), config.dns_strategy())?;
-    match method {
-        Method::Json {path, ..} => body = util::parse_json(body.as_str(), path)?,
-        _ => {}
-    }
-    let ip = util::extract_ip(body.as_str(), method.regex())?;
-    util::parse_ip(config.ip_version(), ip.as_str())
-}
-
-pub fn fetch(env: &Env) -> Vec<(&Method, Result<IpAddr>)> {
-    let mut list = Vec::with_capacity(env.config().methods().len());
-    for method in env.config().methods() {
-        let result = get_globalip(method, env.config());
-        debug!("fetch: result - {:?},  method - {:?}", &result, method);
-        list.push((method, result));
-    }
-    list
+#[derive(Debug)]
+pub enum Outcome {
+    Unchanged(IpAddr),
+    Updated(IpAddr, Option<IpAddr>),
+    /// Every method was skipped by its `min_interval`, so nothing was fetched.
+    Skipped,
 }
 
-pub fn process_fetch_result(list: Vec<(&Method, Result<IpAddr>)>) -> Result<IpAddr> {
-    let results: Vec<(&Method, &IpAddr)> = list.iter()
-        .filter_map(|(&ref method, addr_result)| {
-            match addr_result {
-                Ok(addr) => Some((method, addr)),
-                Err(e) => {
-                    warn!("process_fetch_result: Failed to fetch method - {}", method.url());
-                    warn!("process_fetch_result: error - {}", e);
-                    let mut source = std::error::Error::source(e);
-                    loop {
-                        match source {
-                            Some(err) => {
-                                warn!("process_fetch_result: error source - {}", err);
-                                source = err.source();
-                            }
-                            None => break,
-                        }
-                    }
-                    None
-                }
-            }
-        }).collect();
-    if results.is_empty() {
-        return Err(err!("process_fetch_result: Global IP address not found"));
-    }
-    let mut counter = Vec::<(&IpAddr, Cell<f64>)>::new();
-    for (method, addr) in &results {
-        info!("process_fetch_result: Global IP address {} found - {}", addr, method.url());
-        match counter.iter().find(|t| &t.0 == addr) {
-            Some((_, c)) => c.set(c.get() + method.weight()),
-            None => counter.push((addr, Cell::new(method.weight()))),
-        };
-    }
-    counter.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap().reverse());
-    debug!("process_fetch_result: counter - {:?}", &counter);
-    if counter.len() > 1 {
-        warn!("process_fetch_result: Different addresses detected");
-        for (&addr, count) in &counter {
-            warn!("process_fetch_result: address - {}, count - {}", addr, count.get());
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Outcome::Unchanged(addr) => write!(f, "unchanged {}", addr),
+            Outcome::Updated(addr, Some(old)) => write!(f, "updated {} to {}", old, addr),
+            Outcome::Updated(addr, None) => write!(f, "updated to {}", addr),
+            Outcome::Skipped => write!(f, "skipped, all methods within min_interval"),
         }
     }
-    Ok(counter[0].0.clone())
 }
 
-pub fn find_old_addr(env: &Env) -> Option<IpAddr> {
-    let path = env.output_path();
+pub fn find_old_addr(profile: &Profile) -> Option<IpAddr> {
+    if let Some(state) = profile
+        .state_path()
+        .and_then(|path| State::load(path, profile.ip_version()))
+    {
+        debug!("find_old_addr: Previous IP address found in state - {}", &state.addr);
+        return Some(state.addr);
+    }
+    let path = profile.output_path();
     if !path.is_file() {
         debug!("find_old_addr: output file not found - {}", path.display());
         return None;
@@ -107,7 +73,9 @@ pub fn find_old_addr(env: &Env) -> Option<IpAddr> {
             warn!("find_old_addr: decode error - {}", e);
         })
         .ok()?;
-    let addr = util::parse_ip(env.config().ip_version(), ip.as_str())
+    let addr = profile
+        .ip_version()
+        .parse(ip.as_str())
         .map_err(|e| {
             warn!("find_old_addr: Failed to parse previous output - {}", ip.as_str());
             warn!("find_old_addr: parse error - {}", e);
@@ -117,20 +85,291 @@ pub fn find_old_addr(env: &Env) -> Option<IpAddr> {
     Some(addr)
 }
 
-pub fn output(env: &Env, addr: &IpAddr, old_addr: &Option<&IpAddr>) -> Result<()> {
-    if old_addr.is_some() && old_addr.unwrap() == addr {
+fn network_prefix(addr: &IpAddr, prefix_len: u8) -> IpAddr {
+    match addr {
+        IpAddr::V4(v4) => {
+            let len = u32::from(prefix_len.min(32));
+            let mask = u32::MAX.checked_shl(32 - len).unwrap_or(0);
+            IpAddr::V4(Ipv4Addr::from(u32::from(*v4) & mask))
+        }
+        IpAddr::V6(v6) => {
+            let len = u32::from(prefix_len.min(128));
+            let mask = u128::MAX.checked_shl(128 - len).unwrap_or(0);
+            IpAddr::V6(Ipv6Addr::from(u128::from(*v6) & mask))
+        }
+    }
+}
+
+fn render_template(template: &str, vars: &HashMap<&str, String>) -> Result<String> {
+    let mut rendered = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rendered.push_str(&rest[..start]);
+        let end = rest[start..]
+            .find("}}")
+            .ok_or_else(|| err!("render_template: Unclosed variable - {}", &rest[start..]))?;
+        let name = rest[start + 2..start + end].trim();
+        let value = vars
+            .get(name)
+            .ok_or_else(|| err!("render_template: Unknown variable - {}", name))?;
+        rendered.push_str(value);
+        rest = &rest[start + end + 2..];
+    }
+    rendered.push_str(rest);
+    Ok(rendered)
+}
+
+fn render_output(
+    profile: &Profile,
+    output: &TemplateOutput,
+    addr: &IpAddr,
+    old_addr: &Option<&IpAddr>,
+    now: &DateTime<Utc>,
+) -> Result<()> {
+    let template_path = output.template();
+    let template = fs::read_to_string(template_path).map_err(|e| {
+        err_io!(e, "render_output: Failed to read template - {}", template_path.display())
+    })?;
+    let prefix_len = output.prefix_len(profile.ip_version());
+    let mut vars = HashMap::new();
+    vars.insert("addr", format!("{}", addr));
+    vars.insert("old_addr", old_addr.map(|a| format!("{}", a)).unwrap_or_default());
+    vars.insert("prefix", format!("{}", network_prefix(addr, prefix_len)));
+    vars.insert("prefix_len", format!("{}", prefix_len));
+    vars.insert("version", profile.ip_version().name().to_owned());
+    vars.insert("profile", profile.name().to_owned());
+    vars.insert("timestamp", now.to_rfc3339());
+    let rendered = render_template(template.as_str(), &vars).map_err(|e| {
+        err!("render_output: Failed to render {} - {}", template_path.display(), e)
+    })?;
+    let path = output.destination();
+    write_atomic(path, rendered.as_bytes(), output.options())?;
+    info!("render_output: Rendered {} - {}", template_path.display(), path.display());
+    Ok(())
+}
+
+pub fn output(profile: &Profile, addr: &IpAddr, old_addr: &Option<&IpAddr>) -> Result<bool> {
+    let path = profile.output_path();
+    let now = Utc::now();
+    if old_addr.is_some() && old_addr.unwrap() == addr && path.is_file() {
+        // Destinations added to the config or deleted since the last change.
+        for template_output in profile.outputs().iter().filter(|o| !o.destination().is_file()) {
+            render_output(profile, template_output, addr, old_addr, &now)?;
+        }
         info!("output: Up to date - {}", addr);
-        return Ok(());
-    }
-    let path = env.output_path();
-    let mut file = File::create(path)
-        .map_err(|e| err_io!(e, "output: Failed to create output file - {}", path.display()))?;
-    let mut buf = format!("{}", addr).into_bytes();
-    file.write_all(buf.as_mut())
-        .map_err(|e| err_io!(e, "output: Failed to write {} to {}", addr, path.display()))?;
+        return Ok(false);
+    }
+    for template_output in profile.outputs() {
+        render_output(profile, template_output, addr, old_addr, &now)?;
+    }
+    write_atomic(path, format!("{}", addr).as_bytes(), profile.output_options())?;
     match old_addr {
         Some(old) => info!("output: Updated {} to {} - {}", old, addr, path.display()),
         None => info!("output: Updated to {} - {}", addr, path.display()),
     }
-    Ok(())
+    Ok(true)
+}
+
+fn last_change(profile: &Profile, state: Option<&State>) -> Option<DateTime<Utc>> {
+    if let Some(state) = state {
+        return Some(state.first_seen);
+    }
+    let modified = fs::metadata(profile.output_path()).and_then(|m| m.modified()).ok()?;
+    Some(DateTime::<Utc>::from(modified))
+}
+
+pub fn run(env: &Env, profile: &Profile, registry: &Registry) -> Result<Outcome> {
+    info!("run: start processing profile - {}", profile.name());
+    let scoring = env.config().scoring();
+    let mut board = scoring.map(|options| Scoreboard::load(&env.dir().join(options.file())));
+    let old_addr = find_old_addr(profile);
+    let mut queries = if profile.methods().iter().any(|method| method.min_interval().is_some()) {
+        Some(QueryLog::load(env.dir()))
+    } else {
+        None
+    };
+    let resolver = Resolver::new(env.config(), profile)
+        .previous(old_addr)
+        .scoreboard(board.as_ref());
+    let (records, consensus) = resolver.evaluate(queries.as_mut());
+    if let Some(ref queries) = queries {
+        if let Err(ref e) = queries.save(env.dir()) {
+            log_error!(Level::Warn, e, "run: Failed to save query times");
+        }
+    }
+    registry.record_fetch(profile, &records);
+    if let (Some(options), Some(board)) = (scoring, board.as_mut()) {
+        board.observe(profile, &records, consensus.as_ref().ok().map(|c| &c.addr), Utc::now());
+        if let Err(ref e) = board.save(&env.dir().join(options.file())) {
+            log_error!(Level::Warn, e, "run: Failed to save scores");
+        }
+    }
+    let consensus = match consensus {
+        Err(ref e) if e.kind() == ErrorKind::Throttled => {
+            info!("run: All methods skipped by min_interval - {}", profile.name());
+            return Ok(Outcome::Skipped);
+        }
+        consensus => consensus?,
+    };
+    registry.record_consensus(profile, &consensus);
+    let addr = consensus.addr;
+    let _addr = logging::with_addr(&addr);
+    let split = consensus.candidates.len() > 1;
+    if split {
+        hook::run(env, profile, HookEvent::Disagreement, &addr, old_addr.as_ref(), &consensus.candidates);
+    }
+    let resolution = Resolution::new(profile, records, consensus)?;
+    if split && profile.split_vote() == SplitVote::Block && old_addr != Some(addr) {
+        return Err(err!("run: Split vote blocks update to {}", addr).with_kind(ErrorKind::Consensus));
+    }
+    let updated = output(profile, &addr, &old_addr.as_ref())
+        .map_err(|e| e.with_kind(ErrorKind::Output))?;
+    let now = Utc::now();
+    let mut state = None;
+    if let Some(path) = profile.state_path() {
+        let previous = State::load(path, profile.ip_version());
+        let current = State::new(
+            previous.as_ref(),
+            profile.ip_version(),
+            resolution.consensus.clone(),
+            resolution.methods,
+            now,
+        );
+        current
+            .save(path, profile.output_options())
+            .map_err(|e| e.with_kind(ErrorKind::Output))?;
+        state = Some(current);
+    }
+    let changed_at = if updated {
+        Some(now)
+    } else if registry.last_change(profile).is_none() {
+        last_change(profile, state.as_ref())
+    } else {
+        None
+    };
+    registry.record_success(profile, changed_at, now);
+    if !updated {
+        return Ok(Outcome::Unchanged(addr));
+    }
+    hook::run(env, profile, HookEvent::Change, &addr, old_addr.as_ref(), &resolution.consensus.candidates);
+    Ok(Outcome::Updated(addr, old_addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use globalip_memo::Config;
+    use std::path::Path;
+
+    fn setup(dir: &Path, split_vote: &str) -> Env {
+        setup_methods(dir, split_vote, 0)
+    }
+
+    fn setup_methods(dir: &Path, split_vote: &str, min_interval: u64) -> Env {
+        let _ = fs::remove_dir_all(dir);
+        fs::create_dir_all(dir).unwrap();
+        let mut methods = Vec::new();
+        for (name, addr) in &[("a", "192.0.2.1"), ("b", "192.0.2.1"), ("c", "192.0.2.2")] {
+            let path = dir.join(name);
+            fs::write(&path, addr).unwrap();
+            methods.push(format!(
+                r#"{{"type": "file", "path": "{}", "min_interval": {}}}"#,
+                path.display(),
+                min_interval
+            ));
+        }
+        let text = format!(
+            r#"{{
+                "methods": [{}],
+                "split_vote": "{}",
+                "hooks": [{{
+                    "on": "disagreement",
+                    "command": "/bin/sh",
+                    "args": ["-c", "echo $GLOBALIP_MEMO_EVENT $GLOBALIP_MEMO_ADDR > disagreement.txt"]
+                }}]
+            }}"#,
+            methods.join(", "),
+            split_vote
+        );
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        Env::from_config(dir.to_path_buf(), config).unwrap()
+    }
+
+    #[test]
+    fn test_render_template() {
+        let addr: IpAddr = "2001:db8:1:2:3:4:5:6".parse().unwrap();
+        let mut vars = HashMap::new();
+        vars.insert("addr", format!("{}", addr));
+        vars.insert("prefix", format!("{}", network_prefix(&addr, 64)));
+        let rendered = render_template("allow {{addr}}; # {{ prefix }}/64\n", &vars);
+        assert_eq!(rendered.unwrap(), "allow 2001:db8:1:2:3:4:5:6; # 2001:db8:1:2::/64\n");
+        assert!(render_template("{{unknown}}", &vars).is_err());
+        assert!(render_template("{{addr", &vars).is_err());
+
+        let addr: IpAddr = "192.0.2.77".parse().unwrap();
+        assert_eq!(format!("{}", network_prefix(&addr, 24)), "192.0.2.0");
+        assert_eq!(format!("{}", network_prefix(&addr, 0)), "0.0.0.0");
+        assert_eq!(format!("{}", network_prefix(&addr, 32)), "192.0.2.77");
+    }
+
+    #[test]
+    fn test_run_split_vote() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-split-{}", std::process::id()));
+        let winner: IpAddr = "192.0.2.1".parse().unwrap();
+
+        let env = setup(&dir, "update");
+        let profile = env.select_profiles(&[]).unwrap()[0];
+        let outcome = run(&env, profile, &Registry::default()).unwrap();
+        assert!(matches!(outcome, Outcome::Updated(addr, None) if addr == winner));
+        let event = fs::read_to_string(dir.join("disagreement.txt")).unwrap();
+        assert_eq!(event.trim(), "disagreement 192.0.2.1");
+
+        let env = setup(&dir, "block");
+        let profile = env.select_profiles(&[]).unwrap()[0];
+        let e = run(&env, profile, &Registry::default()).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Consensus);
+        assert!(dir.join("disagreement.txt").is_file());
+        assert!(!profile.output_path().exists());
+
+        fs::write(profile.output_path(), "192.0.2.1").unwrap();
+        let outcome = run(&env, profile, &Registry::default()).unwrap();
+        assert!(matches!(outcome, Outcome::Unchanged(addr) if addr == winner));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_run_throttled() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-throttled-{}", std::process::id()));
+        let env = setup_methods(&dir, "update", 3600);
+        let profile = env.select_profiles(&[]).unwrap()[0];
+        let outcome = run(&env, profile, &Registry::default()).unwrap();
+        assert!(matches!(outcome, Outcome::Updated(..)));
+        let outcome = run(&env, profile, &Registry::default()).unwrap();
+        assert!(matches!(outcome, Outcome::Skipped));
+        assert_eq!(fs::read_to_string(profile.output_path()).unwrap().trim(), "192.0.2.1");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_output_missing_template() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-template-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("allow.tmpl"), "allow {{addr}};\n").unwrap();
+        let text = r#"{
+            "methods": [{"type": "file", "path": "wan.txt"}],
+            "outputs": [{"template": "allow.tmpl", "destination": "allow.conf"}]
+        }"#;
+        let env = Env::from_config(dir.clone(), Config::from_reader(text.as_bytes()).unwrap()).unwrap();
+        let profile = env.select_profiles(&[]).unwrap()[0];
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        fs::write(profile.output_path(), "192.0.2.1").unwrap();
+
+        assert!(!output(profile, &addr, &Some(&addr)).unwrap());
+        assert_eq!(fs::read_to_string(dir.join("allow.conf")).unwrap(), "allow 192.0.2.1;\n");
+        fs::write(dir.join("allow.conf"), "kept\n").unwrap();
+        assert!(!output(profile, &addr, &Some(&addr)).unwrap());
+        assert_eq!(fs::read_to_string(dir.join("allow.conf")).unwrap(), "kept\n");
+        fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -0,0 +1,113 @@
+use crate::env::{IpVersion, METHOD_TYPES};
+use crate::error::{Error, ErrorKind, Result};
+use crate::future::blocking;
+use futures::future::Future;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+type Factory = fn(Value) -> serde_json::Result<Arc<dyn Provider>>;
+
+static REGISTRY: Mutex<BTreeMap<String, Factory>> = Mutex::new(BTreeMap::new());
+
+/// Source of the global IP address for a custom method type.
+///
+/// The returned text goes through the `regex` of the method entry and is then
+/// parsed as an address of the profile's IP version.
+pub trait Provider: fmt::Debug + Send + Sync + 'static {
+    fn fetch(&self, ip_version: &IpVersion) -> Result<String>;
+
+    /// Used by `Resolver::resolve_async`; runs `fetch` on a thread of its own unless overridden.
+    fn fetch_async(self: Arc<Self>, ip_version: &IpVersion) -> Box<dyn Future<Item = String, Error = Error> + Send> {
+        let ip_version = ip_version.clone();
+        blocking(move || self.fetch(&ip_version))
+    }
+}
+
+fn build<T>(value: Value) -> serde_json::Result<Arc<dyn Provider>>
+where
+    T: Provider + DeserializeOwned + 'static,
+{
+    serde_json::from_value::<T>(value).map(|provider| Arc::new(provider) as Arc<dyn Provider>)
+}
+
+/// Registers a method type; config entries with this `type` are deserialized into `T`.
+///
+/// The entry also accepts the common `url`, `regex`, `weight` and `min_interval`
+/// fields, which are removed before deserializing `T`, so it may deny unknown
+/// fields. `url` identifies the method in records, scores and metrics and
+/// defaults to the type name. Types must be registered before the config is loaded.
+pub fn register_provider<T>(type_name: &str) -> Result<()>
+where
+    T: Provider + DeserializeOwned + 'static,
+{
+    if METHOD_TYPES.contains(&type_name) {
+        return Err(err!("register_provider: Built-in method type - {}", type_name).with_kind(ErrorKind::Config));
+    }
+    let mut registry = REGISTRY.lock().unwrap_or_else(|e| e.into_inner());
+    registry.insert(type_name.to_owned(), build::<T>);
+    debug!("register_provider: Method type registered - {}", type_name);
+    Ok(())
+}
+
+pub(crate) fn create(type_name: &str, value: Value) -> Option<serde_json::Result<Arc<dyn Provider>>> {
+    let factory = *REGISTRY.lock().unwrap_or_else(|e| e.into_inner()).get(type_name)?;
+    Some(factory(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::{Config, Method};
+    use crate::resolver::Resolver;
+    use serde::Deserialize;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[derive(Debug, Deserialize)]
+    #[serde(deny_unknown_fields)]
+    struct Inventory {
+        host: String,
+    }
+
+    impl Provider for Inventory {
+        fn fetch(&self, _: &IpVersion) -> Result<String> {
+            match self.host.as_str() {
+                "gateway" => Ok("address=203.0.113.7".to_owned()),
+                host => Err(err!("Inventory::fetch: Unknown host - {}", host)),
+            }
+        }
+    }
+
+    #[test]
+    fn test_register_provider() {
+        assert!(register_provider::<Inventory>("plain").is_err());
+        register_provider::<Inventory>("inventory").unwrap();
+
+        let text = r#"{"type": "inventory", "host": "gateway", "regex": "address=(?P<ip>.+)", "weight": 2}"#;
+        let method: Method = serde_json::from_str(text).unwrap();
+        assert_eq!(method.url(), "inventory");
+        assert_eq!(method.weight(), 2f64);
+        assert!(method.http().is_none());
+        assert!(serde_json::from_str::<Method>(r#"{"type": "inventory"}"#).is_err());
+        assert!(serde_json::from_str::<Method>(r#"{"type": "inventory", "host": "gateway", "port": 22}"#).is_err());
+        assert!(serde_json::from_str::<Method>(r#"{"type": "unknown"}"#).is_err());
+
+        let text = r#"{
+            "methods": [
+                {"type": "inventory", "url": "inventory://gateway", "host": "gateway", "regex": "address=(?P<ip>.+)"},
+                {"type": "inventory", "url": "inventory://missing", "host": "missing"}
+            ]
+        }"#;
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        let resolution = Resolver::new(&config, &profile).resolve().unwrap();
+        assert_eq!(resolution.addr, IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)));
+        assert_eq!(resolution.methods[0].url, "inventory://gateway");
+        assert!(resolution.methods[1].error.is_some());
+        let resolution = Resolver::new(&config, &profile).resolve_async().0.wait().unwrap();
+        assert_eq!(resolution.addr, IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)));
+        assert!(resolution.methods[1].error.is_some());
+    }
+}
@@ -0,0 +1,97 @@
+use crate::env::{FileOptions, IpVersion, Method};
+use crate::error::Result;
+use crate::util;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+const QUERIES_FILENAME: &str = "globalip-queries.json";
+
+/// Last query time of each method, keyed by IP version and URL.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct QueryLog {
+    #[serde(default)]
+    queries: BTreeMap<String, BTreeMap<String, DateTime<Utc>>>,
+}
+
+fn queries_path(dir: &Path) -> PathBuf {
+    dir.join(QUERIES_FILENAME)
+}
+
+impl QueryLog {
+    pub fn load(dir: &Path) -> Self {
+        let path = queries_path(dir);
+        if !path.is_file() {
+            debug!("QueryLog::load: queries file not found - {}", path.display());
+            return QueryLog::default();
+        }
+        File::open(&path)
+            .map_err(|e| {
+                warn!("QueryLog::load: Failed to open queries file - {}", path.display());
+                warn!("QueryLog::load: open error - {}", e);
+            })
+            .and_then(|file| {
+                serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+                    warn!("QueryLog::load: Failed to parse queries file - {}", path.display());
+                    warn!("QueryLog::load: parse error - {}", e);
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = queries_path(dir);
+        let mut buf = serde_json::to_vec_pretty(self)
+            .map_err(|e| err_json!(e, "QueryLog::save: Failed to serialize queries - {}", path.display()))?;
+        buf.push(b'\n');
+        util::write_atomic(&path, &buf, &FileOptions::default())?;
+        debug!("QueryLog::save: Queries saved - {}", path.display());
+        Ok(())
+    }
+
+    fn last_query(&self, ip_version: &IpVersion, method: &Method) -> Option<&DateTime<Utc>> {
+        self.queries.get(ip_version.name()).and_then(|urls| urls.get(method.url()))
+    }
+
+    /// Returns the time left until the method may be queried again.
+    pub fn remaining(&self, ip_version: &IpVersion, method: &Method, now: &DateTime<Utc>) -> Option<Duration> {
+        let min_interval = method.min_interval()?;
+        let elapsed = now.signed_duration_since(*self.last_query(ip_version, method)?).to_std().ok()?;
+        min_interval.checked_sub(elapsed).filter(|remaining| *remaining > Duration::from_secs(0))
+    }
+
+    pub fn record(&mut self, ip_version: &IpVersion, method: &Method, now: DateTime<Utc>) {
+        self.queries
+            .entry(ip_version.name().to_owned())
+            .or_default()
+            .insert(method.url().to_owned(), now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remaining() {
+        let method: Method = serde_json::from_str(
+            r#"{"type": "plain", "url": "https://example.com/", "min_interval": 60}"#,
+        )
+        .unwrap();
+        let now = Utc::now();
+        let mut log = QueryLog::default();
+        assert!(log.remaining(&IpVersion::IPv4, &method, &now).is_none());
+
+        log.record(&IpVersion::IPv4, &method, now - chrono::Duration::seconds(20));
+        let remaining = log.remaining(&IpVersion::IPv4, &method, &now).unwrap();
+        assert_eq!(remaining.as_secs(), 40);
+        assert!(log.remaining(&IpVersion::IPv6, &method, &now).is_none());
+
+        log.record(&IpVersion::IPv4, &method, now - chrono::Duration::seconds(61));
+        assert!(log.remaining(&IpVersion::IPv4, &method, &now).is_none());
+    }
+}
@@ -0,0 +1,623 @@
+use crate::env::{Config, FetchOrder, HttpOptions, IpVersion, Method, Profile, Strategy, TiePolicy};
+use crate::error::{ErrorKind, Result};
+use crate::exec;
+use crate::future::{self, CancelHandle, ResolveFuture};
+use crate::logging;
+use crate::net;
+use crate::ratelimit::QueryLog;
+use crate::score::Scoreboard;
+use crate::state::{Candidate, Consensus, MethodRecord};
+use crate::util;
+use chrono::Utc;
+use log::Level;
+use std::net::IpAddr;
+use std::process;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug)]
+pub(crate) struct FetchResult {
+    pub(crate) method: Arc<Method>,
+    pub(crate) weight: f64,
+    pub(crate) result: Result<IpAddr>,
+    pub(crate) latency: Duration,
+}
+
+impl FetchResult {
+    fn record(&self, profile: &Profile) -> MethodRecord {
+        MethodRecord {
+            index: profile
+                .methods()
+                .iter()
+                .position(|method| Arc::ptr_eq(method, &self.method))
+                .unwrap_or_default(),
+            url: self.method.url().to_owned(),
+            weight: self.weight,
+            latency_ms: self.latency.as_millis() as u64,
+            addr: self.result.as_ref().ok().cloned(),
+            error: self.result.as_ref().err().map(|e| format!("{}", e)),
+        }
+    }
+}
+
+/// Merges the method options over the global ones. `bind_address` and
+/// `interface` are taken together from the method when it sets either.
+pub(crate) fn transport<'a>(http: &'a HttpOptions, profile: &'a Profile, config: &'a Config) -> net::Transport<'a> {
+    let (bind_address, interface) = if http.bind_address().is_some() || http.interface().is_some() {
+        (http.bind_address(), http.interface())
+    } else {
+        (config.bind_address(), config.interface())
+    };
+    net::Transport {
+        ip_version: profile.ip_version(),
+        proxy: http.proxy().or_else(|| config.proxy()),
+        tls: http.tls().or_else(|| config.tls()),
+        bind_address,
+        interface,
+    }
+}
+
+pub(crate) fn parse_body(method: &Method, ip_version: &IpVersion, mut body: String) -> Result<IpAddr> {
+    let path = match method {
+        Method::Json { path, .. } => Some(path),
+        Method::File { json_path, .. } => json_path.as_ref(),
+        _ => None,
+    };
+    if let Some(path) = path {
+        body = util::parse_json(body.as_str(), path)?;
+    }
+    match method {
+        Method::Xml { path, namespaces, .. } => body = util::parse_xml(body.as_str(), path, namespaces)?,
+        Method::Html { selector, attribute, .. } => {
+            body = util::parse_html(body.as_str(), selector, attribute.as_deref())?
+        }
+        _ => {}
+    }
+    let ip = util::extract_ip(body.as_str(), method.regex())?;
+    util::parse_ip(ip_version, ip.as_str())
+}
+
+fn get_globalip(method: &Method, profile: &Profile, config: &Config) -> Result<IpAddr> {
+    let body = match method {
+        Method::Plain { http, .. }
+        | Method::Json { http, .. }
+        | Method::Xml { http, .. }
+        | Method::Html { http, .. } => {
+            util::get_body(method.url(), http, &transport(http, profile, config))?
+        }
+        Method::Exec { exec, .. } => exec::run(exec, profile.ip_version(), profile.dir())?,
+        Method::File { path, .. } => util::read_file(path, method.max_age())?,
+        Method::Custom { provider, .. } => provider.fetch(profile.ip_version())?,
+    };
+    parse_body(method, profile.ip_version(), body)
+}
+
+pub(crate) fn weight(config: &Config, profile: &Profile, board: Option<&Scoreboard>, method: &Method) -> f64 {
+    match (config.scoring(), board) {
+        (Some(options), Some(board)) => board.effective_weight(options, profile, method),
+        _ => method.weight(),
+    }
+}
+
+fn fetch_one(config: &Config, profile: &Profile, board: Option<&Scoreboard>, method: &Arc<Method>) -> FetchResult {
+    let _url = logging::with_url(method.url());
+    let started = Instant::now();
+    let result = get_globalip(method, profile, config);
+    let latency = started.elapsed();
+    debug!("fetch: result - {:?}, latency - {:?}, method - {:?}", &result, latency, method);
+    FetchResult {
+        method: method.clone(),
+        weight: weight(config, profile, board, method),
+        result,
+        latency,
+    }
+}
+
+pub(crate) fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    (nanos ^ (u64::from(process::id()) << 32)) | 1
+}
+
+/// Fisher-Yates shuffle driven by xorshift64.
+pub(crate) fn shuffle<T>(items: &mut [T], seed: u64) {
+    let mut state = if seed == 0 { 1 } else { seed };
+    for i in (1..items.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        items.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+}
+
+/// Address whose accumulated weight reached the target, restricted to the expected address if given.
+pub(crate) fn target_reached(list: &[FetchResult], expected: Option<&IpAddr>, target_weight: f64) -> Option<(IpAddr, f64)> {
+    let mut weights = Vec::<(IpAddr, f64)>::new();
+    for result in list {
+        if let Ok(addr) = result.result {
+            match weights.iter_mut().find(|(a, _)| *a == addr) {
+                Some((_, w)) => *w += result.weight,
+                None => weights.push((addr, result.weight)),
+            }
+        }
+    }
+    weights
+        .into_iter()
+        .filter(|(addr, _)| expected.is_none() || expected == Some(addr))
+        .find(|(_, weight)| *weight >= target_weight)
+}
+
+pub(crate) fn report_target_reached(target_weight: f64, addr: &IpAddr, weight: f64, queried: usize, total: usize) {
+    if queried < total {
+        info!(
+            "fetch: Target weight {} reached by {} with {} after {} of {} methods",
+            target_weight, addr, weight, queried, total
+        );
+    }
+}
+
+fn fetch(
+    config: &Config,
+    profile: &Profile,
+    board: Option<&Scoreboard>,
+    old_addr: Option<&IpAddr>,
+    mut queries: Option<&mut QueryLog>,
+) -> Vec<FetchResult> {
+    let now = Utc::now();
+    let mut methods: Vec<&Arc<Method>> = profile
+        .methods()
+        .iter()
+        .filter(|method| {
+            let remaining = queries
+                .as_ref()
+                .and_then(|queries| queries.remaining(profile.ip_version(), method, &now));
+            match remaining {
+                Some(remaining) => {
+                    let _url = logging::with_url(method.url());
+                    info!(
+                        "fetch: Skipping method, min_interval not elapsed ({}s remaining) - {}",
+                        remaining.as_secs(),
+                        method.url()
+                    );
+                    false
+                }
+                None => true,
+            }
+        })
+        .collect();
+    let mut query = |method: &Arc<Method>| {
+        if let Some(queries) = queries.as_mut() {
+            queries.record(profile.ip_version(), method, Utc::now());
+        }
+        fetch_one(config, profile, board, method)
+    };
+    let (order, target_weight, agree_with_previous) = match profile.strategy() {
+        Strategy::All => return methods.into_iter().map(query).collect(),
+        Strategy::Sequential { order, target_weight, agree_with_previous } => {
+            (order, *target_weight, *agree_with_previous)
+        }
+    };
+    if *order == FetchOrder::Random {
+        shuffle(&mut methods, random_seed());
+    }
+    let expected = if agree_with_previous { old_addr } else { None };
+    let mut list = Vec::with_capacity(methods.len());
+    for method in &methods {
+        list.push(query(method));
+        if let Some((addr, weight)) = target_reached(&list, expected, target_weight) {
+            report_target_reached(target_weight, &addr, weight, list.len(), methods.len());
+            break;
+        }
+    }
+    list
+}
+
+/// Position of the method in the profile, the earlier the higher its priority.
+fn priority(profile: &Profile, method: &Method) -> usize {
+    profile
+        .methods()
+        .iter()
+        .position(|m| std::ptr::eq(m.as_ref(), method))
+        .unwrap_or(usize::MAX)
+}
+
+/// Chooses among candidates sorted by weight and then by method priority.
+fn resolve_tie(candidates: &[Candidate], policy: TiePolicy, old_addr: Option<&IpAddr>) -> Result<usize> {
+    let tied = candidates
+        .iter()
+        .take_while(|c| (c.weight - candidates[0].weight).abs() < f64::EPSILON)
+        .count();
+    if tied < 2 {
+        return Ok(0);
+    }
+    let addrs = candidates[..tied]
+        .iter()
+        .map(|c| format!("{}", c.addr))
+        .collect::<Vec<_>>()
+        .join(", ");
+    match policy {
+        TiePolicy::Refuse => {
+            Err(err!("resolve_tie: Refused to break tie between {}", addrs).with_kind(ErrorKind::Consensus))
+        }
+        TiePolicy::Previous => match candidates[..tied].iter().position(|c| Some(&c.addr) == old_addr) {
+            Some(index) => {
+                info!("resolve_tie: Tie between {} resolved to {} - previous address", addrs, candidates[index].addr);
+                Ok(index)
+            }
+            None => {
+                info!(
+                    "resolve_tie: Tie between {} resolved to {} - previous address not tied, falling back to method priority",
+                    addrs, candidates[0].addr
+                );
+                Ok(0)
+            }
+        },
+        TiePolicy::Priority => {
+            info!("resolve_tie: Tie between {} resolved to {} - method priority", addrs, candidates[0].addr);
+            Ok(0)
+        }
+    }
+}
+
+fn process_fetch_result(list: &[FetchResult], profile: &Profile, old_addr: Option<&IpAddr>) -> Result<Consensus> {
+    let results: Vec<(&Method, f64, &IpAddr)> = list.iter()
+        .filter_map(|FetchResult { method, weight, result, .. }| {
+            match result {
+                Ok(addr) => Some((method.as_ref(), *weight, addr)),
+                Err(e) => {
+                    let _url = logging::with_url(method.url());
+                    log_error!(Level::Warn, e, "process_fetch_result: Failed to fetch method - {}", method.url());
+                    None
+                }
+            }
+        }).collect();
+    if results.is_empty() {
+        return Err(err!("process_fetch_result: Global IP address not found").with_kind(ErrorKind::Fetch));
+    }
+    // Candidates with the priority of their highest priority method.
+    let mut ranked = Vec::<(Candidate, usize)>::new();
+    for (method, weight, addr) in &results {
+        let _url = logging::with_url(method.url());
+        info!("process_fetch_result: Global IP address {} found - {}", addr, method.url());
+        let method_priority = priority(profile, method);
+        match ranked.iter_mut().find(|(c, _)| &&c.addr == addr) {
+            Some((c, p)) => {
+                c.weight += weight;
+                c.methods.push(method.url().to_owned());
+                *p = (*p).min(method_priority);
+            }
+            None => ranked.push((
+                Candidate {
+                    addr: **addr,
+                    weight: *weight,
+                    methods: vec![method.url().to_owned()],
+                },
+                method_priority,
+            )),
+        };
+    }
+    ranked.sort_by(|(a, a_priority), (b, b_priority)| {
+        b.weight
+            .partial_cmp(&a.weight)
+            .unwrap()
+            .then_with(|| a_priority.cmp(b_priority))
+    });
+    let mut candidates: Vec<Candidate> = ranked.into_iter().map(|(c, _)| c).collect();
+    let chosen = resolve_tie(&candidates, profile.tie_policy(), old_addr)?;
+    let chosen = candidates.remove(chosen);
+    candidates.insert(0, chosen);
+    debug!("process_fetch_result: candidates - {:?}", &candidates);
+    Ok(Consensus {
+        addr: candidates[0].addr,
+        weight: candidates[0].weight,
+        total_weight: results.iter().map(|(_, weight, _)| weight).sum(),
+        candidates,
+    })
+}
+
+fn report_disagreement(consensus: &Consensus) {
+    warn!(
+        "report_disagreement: Different addresses detected, {} chosen with weight {} of {}",
+        consensus.addr, consensus.weight, consensus.total_weight
+    );
+    for candidate in &consensus.candidates {
+        let _addr = logging::with_addr(&candidate.addr);
+        warn!(
+            "report_disagreement: address - {}, weight - {}, methods - {}",
+            candidate.addr,
+            candidate.weight,
+            candidate.methods.join(", ")
+        );
+    }
+}
+
+fn check_quorum(profile: &Profile, consensus: &Consensus) -> Result<()> {
+    let required = consensus.total_weight * profile.quorum();
+    if consensus.weight < required {
+        return Err(err!(
+            "check_quorum: Consensus not reached - {} has weight {} of {}, required {}",
+            consensus.addr,
+            consensus.weight,
+            consensus.total_weight,
+            required
+        )
+        .with_kind(ErrorKind::Consensus));
+    }
+    Ok(())
+}
+
+/// Outcome of querying the methods of a profile and voting on their answers.
+#[derive(Debug, Clone)]
+pub struct Resolution {
+    pub addr: IpAddr,
+    pub ip_version: IpVersion,
+    pub consensus: Consensus,
+    pub methods: Vec<MethodRecord>,
+}
+
+impl Resolution {
+    /// Resolution of a consensus from `Resolver::evaluate`, if it meets the quorum of the profile.
+    pub fn new(profile: &Profile, methods: Vec<MethodRecord>, consensus: Consensus) -> Result<Self> {
+        conclude(profile, methods, Ok(consensus))
+    }
+
+    /// Whether the methods returned more than one address.
+    pub fn is_split(&self) -> bool {
+        self.consensus.candidates.len() > 1
+    }
+
+    /// Weight of the chosen address divided by the weight of all responding methods.
+    pub fn agreement_ratio(&self) -> f64 {
+        if self.consensus.total_weight > 0f64 {
+            self.consensus.weight / self.consensus.total_weight
+        } else {
+            0f64
+        }
+    }
+}
+
+/// Runs the methods of a profile and decides on the global IP address.
+///
+/// ```no_run
+/// use globalip_memo::{Config, Resolver};
+///
+/// let config = Config::from_reader(std::fs::File::open("globalip-config.json")?)?;
+/// let profile = config.profile("default")?;
+/// let resolution = Resolver::new(&config, &profile).resolve()?;
+/// println!("{} ({:.0}% agreement)", resolution.addr, resolution.agreement_ratio() * 100f64);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug)]
+pub struct Resolver<'a> {
+    config: &'a Config,
+    profile: &'a Profile,
+    previous: Option<IpAddr>,
+    scoreboard: Option<&'a Scoreboard>,
+}
+
+impl<'a> Resolver<'a> {
+    pub fn new(config: &'a Config, profile: &'a Profile) -> Self {
+        Resolver {
+            config,
+            profile,
+            previous: None,
+            scoreboard: None,
+        }
+    }
+
+    /// Sets the previously known address used by the tie policy and the sequential strategy.
+    pub fn previous(mut self, addr: Option<IpAddr>) -> Self {
+        self.previous = addr;
+        self
+    }
+
+    /// Sets the scores that adjust the method weights when scoring is adaptive.
+    pub fn scoreboard(mut self, scoreboard: Option<&'a Scoreboard>) -> Self {
+        self.scoreboard = scoreboard;
+        self
+    }
+
+    /// Queries the methods and votes without checking the quorum, returning the
+    /// records of the methods even when no address was decided. Methods queried
+    /// within their `min_interval` according to `queries` are skipped, and the
+    /// others are recorded in it.
+    pub fn evaluate(&self, queries: Option<&mut QueryLog>) -> (Vec<MethodRecord>, Result<Consensus>) {
+        let list = fetch(self.config, self.profile, self.scoreboard, self.previous.as_ref(), queries);
+        vote(&list, self.profile, self.previous.as_ref())
+    }
+
+    /// Queries the methods, votes on the address and checks the quorum.
+    pub fn resolve(&self) -> Result<Resolution> {
+        let (methods, consensus) = self.evaluate(None);
+        conclude(self.profile, methods, consensus)
+    }
+
+    /// Non-blocking variant of `resolve` on the async HTTP client, with a handle to cancel it.
+    ///
+    /// Like `resolve`, it takes no [`QueryLog`], so `min_interval` is not applied
+    /// and every method is queried.
+    ///
+    /// Methods that pin public keys or disable the system roots run their request on a
+    /// thread of their own, as their certificate check needs a blocking handshake.
+    pub fn resolve_async(&self) -> (ResolveFuture, CancelHandle) {
+        future::resolve(self.config, self.profile, self.scoreboard, self.previous)
+    }
+}
+
+pub(crate) fn vote(list: &[FetchResult], profile: &Profile, previous: Option<&IpAddr>) -> (Vec<MethodRecord>, Result<Consensus>) {
+    let records = list.iter().map(|r| r.record(profile)).collect();
+    if list.is_empty() {
+        let e = err!("Resolver::evaluate: All methods skipped by min_interval").with_kind(ErrorKind::Throttled);
+        return (records, Err(e));
+    }
+    let consensus = process_fetch_result(list, profile, previous);
+    if let Ok(ref consensus) = consensus {
+        if consensus.candidates.len() > 1 {
+            report_disagreement(consensus);
+        }
+    }
+    (records, consensus)
+}
+
+pub(crate) fn conclude(profile: &Profile, methods: Vec<MethodRecord>, consensus: Result<Consensus>) -> Result<Resolution> {
+    let consensus = consensus?;
+    check_quorum(profile, &consensus)?;
+    Ok(Resolution {
+        addr: consensus.addr,
+        ip_version: profile.ip_version().clone(),
+        consensus,
+        methods,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::Future;
+    use std::fs;
+    use std::net::Ipv4Addr;
+
+    fn candidate(last: u8, weight: f64) -> Candidate {
+        Candidate {
+            addr: IpAddr::V4(Ipv4Addr::new(192, 0, 2, last)),
+            weight,
+            methods: vec![],
+        }
+    }
+
+    #[test]
+    fn test_resolve_tie() {
+        let candidates = vec![candidate(1, 2f64), candidate(2, 2f64), candidate(3, 1f64)];
+        let previous = candidates[1].addr;
+        let other = candidates[2].addr;
+        assert_eq!(resolve_tie(&candidates, TiePolicy::Priority, Some(&previous)).unwrap(), 0);
+        assert_eq!(resolve_tie(&candidates, TiePolicy::Previous, Some(&previous)).unwrap(), 1);
+        assert_eq!(resolve_tie(&candidates, TiePolicy::Previous, Some(&other)).unwrap(), 0);
+        assert_eq!(resolve_tie(&candidates, TiePolicy::Previous, None).unwrap(), 0);
+        let e = resolve_tie(&candidates, TiePolicy::Refuse, None).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::Consensus);
+
+        let decided = vec![candidate(1, 2f64), candidate(2, 1f64)];
+        assert_eq!(resolve_tie(&decided, TiePolicy::Refuse, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_priority() {
+        let text = r#"{"methods": [
+            {"type": "file", "path": "a", "url": "router"},
+            {"type": "file", "path": "b", "url": "router"}
+        ]}"#;
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        let fetched = |index: usize, last: u8| FetchResult {
+            method: profile.methods()[index].clone(),
+            weight: 1f64,
+            result: Ok(IpAddr::V4(Ipv4Addr::new(192, 0, 2, last))),
+            latency: Duration::from_millis(0),
+        };
+        let list = vec![fetched(1, 2), fetched(0, 1)];
+        let consensus = process_fetch_result(&list, &profile, None).unwrap();
+        assert_eq!(consensus.addr, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+    }
+
+    #[test]
+    fn test_transport() {
+        let text = r#"{
+            "interface": "lo",
+            "methods": [
+                {"type": "plain", "url": "https://ifconfig.io/ip", "bind_address": "127.0.0.1"},
+                {"type": "plain", "url": "https://ifconfig.me/ip"}
+            ]
+        }"#;
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        let http = profile.methods()[0].http().unwrap();
+        let merged = transport(http, &profile, &config);
+        assert_eq!(merged.bind_address, Some(IpAddr::V4(Ipv4Addr::LOCALHOST)));
+        assert_eq!(merged.interface, None);
+        let http = profile.methods()[1].http().unwrap();
+        let merged = transport(http, &profile, &config);
+        assert_eq!(merged.bind_address, None);
+        assert_eq!(merged.interface, Some("lo"));
+
+        let text = r#"{"methods": [{"type": "plain", "url": "https://ifconfig.io/ip", "bind_address": "127.0.0.1", "interface": "lo"}]}"#;
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(config.profile("default").unwrap_err().kind(), ErrorKind::Config);
+        let text = r#"{"bind_address": "127.0.0.1", "interface": "lo", "methods": [{"type": "plain", "url": "https://ifconfig.io/ip"}]}"#;
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        assert_eq!(config.profile("default").unwrap_err().kind(), ErrorKind::Config);
+    }
+
+    #[test]
+    fn test_sequential() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-sequential-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let methods = ["192.0.2.1", "192.0.2.1", "192.0.2.2", "192.0.2.2"]
+            .iter()
+            .enumerate()
+            .map(|(i, addr)| {
+                let path = dir.join(format!("{}", i));
+                fs::write(&path, addr).unwrap();
+                format!(r#"{{"type": "file", "path": "{}"}}"#, path.display())
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        let text = format!(
+            r#"{{"methods": [{}], "strategy": {{"type": "sequential", "target_weight": 2}}}}"#,
+            methods
+        );
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        let resolution = Resolver::new(&config, &profile).resolve().unwrap();
+        assert_eq!(resolution.addr, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        assert_eq!(resolution.methods.len(), 2);
+        let resolution = Resolver::new(&config, &profile).resolve_async().0.wait().unwrap();
+        assert_eq!(resolution.methods.len(), 2);
+
+        let text = text.replace(r#""target_weight": 2"#, r#""target_weight": 2, "agree_with_previous": true"#);
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        let previous = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2)));
+        let resolver = Resolver::new(&config, &profile).previous(previous);
+        assert_eq!(resolver.resolve().unwrap().methods.len(), 4);
+        assert_eq!(resolver.resolve_async().0.wait().unwrap().methods.len(), 4);
+        let previous = Some(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let resolver = Resolver::new(&config, &profile).previous(previous);
+        assert_eq!(resolver.resolve().unwrap().methods.len(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_json_path() {
+        let dir = std::env::temp_dir().join(format!("globalip-memo-file-json-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wan.json");
+        fs::write(&path, r#"{"wan": {"ip": "192.0.2.1"}}"#).unwrap();
+        let text = format!(
+            r#"{{"methods": [{{"type": "file", "path": "{}", "json_path": "wan.ip"}}]}}"#,
+            path.display()
+        );
+        let config = Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        assert_eq!(profile.methods()[0].url(), path.to_str().unwrap());
+        let resolution = Resolver::new(&config, &profile).resolve().unwrap();
+        assert_eq!(resolution.addr, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        let resolution = Resolver::new(&config, &profile).resolve_async().0.wait().unwrap();
+        assert_eq!(resolution.addr, IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1)));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_shuffle() {
+        let mut first: Vec<u32> = (0..10).collect();
+        let mut second = first.clone();
+        shuffle(&mut first, 42);
+        shuffle(&mut second, 42);
+        assert_eq!(first, second);
+        assert_ne!(first, (0..10).collect::<Vec<_>>());
+        first.sort();
+        assert_eq!(first, (0..10).collect::<Vec<_>>());
+    }
+}
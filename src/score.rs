@@ -0,0 +1,249 @@
+use crate::env::{FileOptions, Method, Profile, ScoringOptions};
+use crate::error::Result;
+use crate::state::MethodRecord;
+use crate::util;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::Path;
+
+const SMOOTHING: f64 = 0.2;
+
+fn method_key(profile: &Profile, index: usize) -> Option<String> {
+    let methods = profile.methods();
+    let url = methods.get(index)?.url();
+    match methods[..index].iter().filter(|method| method.url() == url).count() {
+        0 => Some(url.to_owned()),
+        n => Some(format!("{} #{}", url, n + 1)),
+    }
+}
+
+fn ewma(current: f64, value: f64, first: bool) -> f64 {
+    if first {
+        value
+    } else {
+        current + SMOOTHING * (value - current)
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MethodStats {
+    pub samples: u64,
+    pub successes: u64,
+    /// Samples compared against a consensus address. Runs without one only
+    /// count towards `samples` and leave `agreement_rate` as it was.
+    #[serde(default)]
+    pub agreements: u64,
+    pub success_rate: f64,
+    pub agreement_rate: f64,
+    pub latency_ms: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl MethodStats {
+    /// `agreed` is `None` when the method failed or no address was decided,
+    /// so the agreement average is seeded by the first comparable sample.
+    fn observe(&mut self, success: bool, agreed: Option<bool>, latency_ms: f64, now: DateTime<Utc>) {
+        let first = self.samples == 0;
+        self.success_rate = ewma(self.success_rate, if success { 1f64 } else { 0f64 }, first);
+        self.latency_ms = ewma(self.latency_ms, latency_ms, first);
+        if let Some(agreed) = agreed {
+            let value = if agreed { 1f64 } else { 0f64 };
+            self.agreement_rate = ewma(self.agreement_rate, value, self.agreements == 0);
+            self.agreements += 1;
+        }
+        if success {
+            self.successes += 1;
+        }
+        self.samples += 1;
+        self.last_seen = Some(now);
+    }
+
+    pub fn score(&self) -> f64 {
+        self.success_rate * self.agreement_rate
+    }
+
+    /// Weight multiplier between the configured floor and ceiling, 1 until enough samples are collected.
+    pub fn multiplier(&self, options: &ScoringOptions) -> f64 {
+        if self.samples < options.min_samples() {
+            return 1f64;
+        }
+        options.floor() + (options.ceiling() - options.floor()) * self.score()
+    }
+}
+
+/// Stats of each profile keyed by method url, numbered from `#2` on when
+/// several methods of the profile share it.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Scoreboard {
+    #[serde(default)]
+    profiles: BTreeMap<String, BTreeMap<String, MethodStats>>,
+}
+
+impl Scoreboard {
+    pub fn load(path: &Path) -> Self {
+        if !path.is_file() {
+            debug!("Scoreboard::load: scores file not found - {}", path.display());
+            return Scoreboard::default();
+        }
+        File::open(path)
+            .map_err(|e| {
+                warn!("Scoreboard::load: Failed to open scores file - {}", path.display());
+                warn!("Scoreboard::load: open error - {}", e);
+            })
+            .and_then(|file| {
+                serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+                    warn!("Scoreboard::load: Failed to parse scores file - {}", path.display());
+                    warn!("Scoreboard::load: parse error - {}", e);
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let mut buf = serde_json::to_vec_pretty(self)
+            .map_err(|e| err_json!(e, "Scoreboard::save: Failed to serialize scores - {}", path.display()))?;
+        buf.push(b'\n');
+        util::write_atomic(path, &buf, &FileOptions::default())?;
+        debug!("Scoreboard::save: Scores saved - {}", path.display());
+        Ok(())
+    }
+
+    pub fn stats(&self, profile: &Profile, method: &Method) -> Option<&MethodStats> {
+        let index = profile
+            .methods()
+            .iter()
+            .position(|m| std::ptr::eq(m.as_ref(), method))?;
+        let key = method_key(profile, index)?;
+        self.profiles.get(profile.name()).and_then(|methods| methods.get(&key))
+    }
+
+    pub fn effective_weight(&self, options: &ScoringOptions, profile: &Profile, method: &Method) -> f64 {
+        if !options.adaptive() {
+            return method.weight();
+        }
+        let multiplier = self
+            .stats(profile, method)
+            .map(|stats| stats.multiplier(options))
+            .unwrap_or(1f64);
+        method.weight() * multiplier
+    }
+
+    pub fn observe(&mut self, profile: &Profile, records: &[MethodRecord], addr: Option<&IpAddr>, now: DateTime<Utc>) {
+        let keys: Vec<String> = (0..profile.methods().len())
+            .filter_map(|index| method_key(profile, index))
+            .collect();
+        let methods = self.profiles.entry(profile.name().to_owned()).or_default();
+        methods.retain(|key, _| keys.contains(key));
+        for record in records {
+            let key = match keys.get(record.index) {
+                Some(key) => key,
+                None => continue,
+            };
+            let found = record.addr.as_ref();
+            let agreed = found.and(addr).map(|addr| found == Some(addr));
+            methods
+                .entry(key.clone())
+                .or_default()
+                .observe(found.is_some(), agreed, record.latency_ms as f64, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_method_stats() {
+        let options: ScoringOptions =
+            serde_json::from_str(r#"{"floor": 0.5, "ceiling": 1.5, "min_samples": 3}"#).unwrap();
+        let now = Utc::now();
+        let mut stats = MethodStats::default();
+        stats.observe(true, Some(true), 100f64, now);
+        stats.observe(true, Some(true), 200f64, now);
+        assert_eq!(stats.multiplier(&options), 1f64);
+
+        stats.observe(true, Some(true), 100f64, now);
+        assert_eq!(stats.samples, 3);
+        assert_eq!(stats.score(), 1f64);
+        assert_eq!(stats.multiplier(&options), 1.5);
+
+        stats.observe(false, None, 0f64, now);
+        stats.observe(true, Some(false), 100f64, now);
+        assert_eq!(stats.successes, 4);
+        assert!(stats.success_rate < 1f64 && stats.success_rate > 0.5);
+        assert!(stats.agreement_rate < 1f64 && stats.agreement_rate > 0.5);
+        let multiplier = stats.multiplier(&options);
+        assert!(multiplier > 0.5 && multiplier < 1.5);
+    }
+
+    #[test]
+    fn test_observe() {
+        let text = r#"{"methods": [{"type": "plain", "url": "https://ifconfig.io/ip"}]}"#;
+        let config = crate::env::Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        let record = |addr: Option<IpAddr>| MethodRecord {
+            index: 0,
+            url: "https://ifconfig.io/ip".to_owned(),
+            weight: 1f64,
+            latency_ms: 100,
+            addr,
+            error: None,
+        };
+        let found: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let now = Utc::now();
+        let mut board = Scoreboard::default();
+        board.observe(&profile, &[record(Some(found))], None, now);
+        let stats = board.stats(&profile, &profile.methods()[0]).unwrap();
+        assert_eq!((stats.samples, stats.successes, stats.agreements), (1, 1, 0));
+        assert_eq!(stats.agreement_rate, 0f64);
+
+        board.observe(&profile, &[record(Some(found))], Some(&other), now);
+        let stats = board.stats(&profile, &profile.methods()[0]).unwrap();
+        assert_eq!((stats.samples, stats.successes, stats.agreements), (2, 2, 1));
+        assert_eq!(stats.agreement_rate, 0f64);
+
+        board.observe(&profile, &[record(None)], Some(&found), now);
+        board.observe(&profile, &[record(Some(found))], Some(&found), now);
+        let stats = board.stats(&profile, &profile.methods()[0]).unwrap();
+        assert_eq!((stats.samples, stats.successes, stats.agreements), (4, 3, 2));
+        assert_eq!(stats.agreement_rate, SMOOTHING);
+    }
+
+    #[test]
+    fn test_method_key() {
+        let text = r#"{"methods": [
+            {"type": "plain", "url": "https://ifconfig.io/ip"},
+            {"type": "plain", "url": "https://ifconfig.me/ip"},
+            {"type": "json", "url": "https://ifconfig.io/ip", "path": "ip"}
+        ]}"#;
+        let config = crate::env::Config::from_reader(text.as_bytes()).unwrap();
+        let profile = config.profile("default").unwrap();
+        assert_eq!(method_key(&profile, 0).unwrap(), "https://ifconfig.io/ip");
+        assert_eq!(method_key(&profile, 2).unwrap(), "https://ifconfig.io/ip #2");
+        assert!(method_key(&profile, 3).is_none());
+
+        let record = |index: usize, addr: &str| MethodRecord {
+            index,
+            url: "https://ifconfig.io/ip".to_owned(),
+            weight: 1f64,
+            latency_ms: 100,
+            addr: Some(addr.parse().unwrap()),
+            error: None,
+        };
+        let addr: IpAddr = "192.0.2.1".parse().unwrap();
+        let mut board = Scoreboard::default();
+        board.observe(&profile, &[record(0, "192.0.2.1"), record(2, "192.0.2.2")], Some(&addr), Utc::now());
+        let plain = board.stats(&profile, &profile.methods()[0]).unwrap();
+        let json = board.stats(&profile, &profile.methods()[2]).unwrap();
+        assert_eq!(plain.agreement_rate, 1f64);
+        assert_eq!(json.agreement_rate, 0f64);
+        assert!(board.stats(&profile, &profile.methods()[1]).is_none());
+    }
+}
@@ -0,0 +1,144 @@
+use crate::env::{FileOptions, IpVersion};
+use crate::error::Result;
+use crate::util;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::BufReader;
+use std::net::IpAddr;
+use std::path::Path;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub addr: IpAddr,
+    pub weight: f64,
+    #[serde(default)]
+    pub methods: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Consensus {
+    pub addr: IpAddr,
+    pub weight: f64,
+    pub total_weight: f64,
+    pub candidates: Vec<Candidate>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MethodRecord {
+    /// Position of the method in the profile.
+    #[serde(default)]
+    pub index: usize,
+    pub url: String,
+    pub weight: f64,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub addr: Option<IpAddr>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct State {
+    pub addr: IpAddr,
+    pub ip_version: IpVersion,
+    pub first_seen: DateTime<Utc>,
+    pub last_confirmed: DateTime<Utc>,
+    pub methods: Vec<MethodRecord>,
+    pub consensus: Consensus,
+}
+
+impl State {
+    pub fn new(
+        previous: Option<&State>,
+        ip_version: &IpVersion,
+        consensus: Consensus,
+        methods: Vec<MethodRecord>,
+        now: DateTime<Utc>,
+    ) -> Self {
+        let first_seen = previous
+            .filter(|state| state.addr == consensus.addr)
+            .map(|state| state.first_seen)
+            .unwrap_or(now);
+        State {
+            addr: consensus.addr,
+            ip_version: ip_version.clone(),
+            first_seen,
+            last_confirmed: now,
+            methods,
+            consensus,
+        }
+    }
+
+    /// Reads the state file, ignoring one that is missing, unreadable or of another IP version.
+    pub fn load(path: &Path, ip_version: &IpVersion) -> Option<State> {
+        if !path.is_file() {
+            debug!("State::load: state file not found - {}", path.display());
+            return None;
+        }
+        let file = File::open(path)
+            .map_err(|e| {
+                warn!("State::load: Failed to open state file - {}", path.display());
+                warn!("State::load: open error - {}", e);
+            })
+            .ok()?;
+        let state: State = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| {
+                warn!("State::load: Failed to parse state file - {}", path.display());
+                warn!("State::load: parse error - {}", e);
+            })
+            .ok()?;
+        if &state.ip_version != ip_version {
+            warn!("State::load: State file is not {} - {}", ip_version, path.display());
+            return None;
+        }
+        Some(state)
+    }
+
+    pub fn save(&self, path: &Path, options: &FileOptions) -> Result<()> {
+        let mut buf = serde_json::to_vec_pretty(self)
+            .map_err(|e| err_json!(e, "State::save: Failed to serialize state - {}", path.display()))?;
+        buf.push(b'\n');
+        util::write_atomic(path, &buf, options)?;
+        debug!("State::save: State saved - {}", path.display());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+    use std::net::Ipv4Addr;
+
+    fn consensus(addr: IpAddr) -> Consensus {
+        Consensus {
+            addr,
+            weight: 1f64,
+            total_weight: 1f64,
+            candidates: vec![Candidate { addr, weight: 1f64, methods: vec![] }],
+        }
+    }
+
+    #[test]
+    fn test_state() {
+        let addr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let then = Utc::now() - Duration::hours(1);
+        let now = Utc::now();
+        let first = State::new(None, &IpVersion::IPv4, consensus(addr), vec![], then);
+        assert_eq!(first.first_seen, then);
+
+        let second = State::new(Some(&first), &IpVersion::IPv4, consensus(addr), vec![], now);
+        assert_eq!(second.first_seen, then);
+        assert_eq!(second.last_confirmed, now);
+
+        let other = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2));
+        let third = State::new(Some(&second), &IpVersion::IPv4, consensus(other), vec![], now);
+        assert_eq!(third.first_seen, now);
+
+        let text = serde_json::to_string(&third).unwrap();
+        let parsed: State = serde_json::from_str(&text).unwrap();
+        assert_eq!(parsed.addr, other);
+        assert_eq!(parsed.ip_version, IpVersion::IPv4);
+    }
+}
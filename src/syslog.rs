@@ -0,0 +1,227 @@
+use chrono::{Local, SecondsFormat, Utc};
+use log::Level;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::process;
+use std::time::Duration;
+
+const LOCAL_SOCKET: &str = "/dev/log";
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const NIL: &str = "-";
+
+const FACILITIES: &[(&str, u8)] = &[
+    ("kern", 0),
+    ("user", 1),
+    ("mail", 2),
+    ("daemon", 3),
+    ("auth", 4),
+    ("syslog", 5),
+    ("lpr", 6),
+    ("news", 7),
+    ("uucp", 8),
+    ("cron", 9),
+    ("authpriv", 10),
+    ("ftp", 11),
+    ("local0", 16),
+    ("local1", 17),
+    ("local2", 18),
+    ("local3", 19),
+    ("local4", 20),
+    ("local5", 21),
+    ("local6", 22),
+    ("local7", 23),
+];
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Error => 3,
+        Level::Warn => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return NIL.to_owned();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    match String::from_utf8_lossy(&buf[..len]).into_owned() {
+        ref name if name.is_empty() => NIL.to_owned(),
+        name => name,
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    Unix(UnixDatagram, PathBuf),
+    Udp(UdpSocket),
+    Tcp(String, Option<TcpStream>),
+}
+
+fn connect_tcp(addr: &str) -> io::Result<TcpStream> {
+    let mut last = io::Error::new(io::ErrorKind::NotFound, format!("address not resolved - {}", addr));
+    for sock_addr in addr.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&sock_addr, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last = e,
+        }
+    }
+    Err(last)
+}
+
+/// Sends log messages to a local syslog socket or a remote RFC 5424 collector.
+#[derive(Debug)]
+pub struct Syslog {
+    transport: Transport,
+    facility: u8,
+    app_name: String,
+    hostname: String,
+    pid: u32,
+}
+
+impl Syslog {
+    /// Creates a sender for `local`, `unix:PATH`, `udp://HOST:PORT` or `tcp://HOST:PORT`.
+    pub fn new(target: &str, facility: &str, app_name: &str) -> io::Result<Self> {
+        let facility = FACILITIES
+            .iter()
+            .find(|(name, _)| *name == facility)
+            .map(|(_, code)| *code)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, format!("unknown facility - {}", facility))
+            })?;
+        let transport = if target == "local" {
+            Transport::Unix(UnixDatagram::unbound()?, PathBuf::from(LOCAL_SOCKET))
+        } else if let Some(path) = target.strip_prefix("unix:") {
+            Transport::Unix(UnixDatagram::unbound()?, PathBuf::from(path))
+        } else if let Some(addr) = target.strip_prefix("udp://") {
+            let remote = addr.to_socket_addrs()?.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::NotFound, format!("address not resolved - {}", addr))
+            })?;
+            let local = if remote.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+            let socket = UdpSocket::bind(local)?;
+            socket.connect(remote)?;
+            Transport::Udp(socket)
+        } else if let Some(addr) = target.strip_prefix("tcp://") {
+            Transport::Tcp(addr.to_owned(), None)
+        } else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown syslog target - {}", target),
+            ));
+        };
+        Ok(Syslog {
+            transport,
+            facility,
+            app_name: app_name.to_owned(),
+            hostname: hostname(),
+            pid: process::id(),
+        })
+    }
+
+    fn priority(&self, level: Level) -> u8 {
+        self.facility * 8 + severity(level)
+    }
+
+    /// Formats a message in the traditional format understood by local syslog daemons.
+    fn format_local(&self, level: Level, message: &str) -> String {
+        format!(
+            "<{}>{} {}[{}]: {}",
+            self.priority(level),
+            Local::now().format("%b %e %H:%M:%S"),
+            self.app_name,
+            self.pid,
+            message
+        )
+    }
+
+    /// Formats a message as an RFC 5424 syslog message.
+    fn format_remote(&self, level: Level, message: &str) -> String {
+        format!(
+            "<{}>1 {} {} {} {} {} {} {}",
+            self.priority(level),
+            Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.hostname,
+            self.app_name,
+            self.pid,
+            NIL,
+            NIL,
+            message
+        )
+    }
+
+    pub fn send(&mut self, level: Level, message: &str) -> io::Result<()> {
+        let data = match self.transport {
+            Transport::Unix(..) => self.format_local(level, message),
+            _ => self.format_remote(level, message),
+        };
+        match &mut self.transport {
+            Transport::Unix(socket, path) => {
+                socket.send_to(data.as_bytes(), path)?;
+            }
+            Transport::Udp(socket) => {
+                socket.send(data.as_bytes())?;
+            }
+            Transport::Tcp(addr, stream) => {
+                // RFC 6587 octet counting
+                let frame = format!("{} {}", data.len(), data);
+                if let Some(s) = stream {
+                    if s.write_all(frame.as_bytes()).is_ok() {
+                        return Ok(());
+                    }
+                }
+                let mut s = connect_tcp(addr)?;
+                s.write_all(frame.as_bytes())?;
+                *stream = Some(s);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_local_socket() {
+        let path = std::env::temp_dir().join(format!("globalip-memo-syslog-{}", process::id()));
+        let _ = fs::remove_file(&path);
+        let server = UnixDatagram::bind(&path).unwrap();
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let target = format!("unix:{}", path.display());
+        let mut syslog = Syslog::new(&target, "daemon", "memo-test").unwrap();
+        let mut buf = [0u8; 1024];
+        let levels = [
+            (Level::Error, "<27>"),
+            (Level::Warn, "<28>"),
+            (Level::Info, "<30>"),
+            (Level::Debug, "<31>"),
+        ];
+        for (level, priority) in &levels {
+            syslog.send(*level, "hello").unwrap();
+            let len = server.recv(&mut buf).unwrap();
+            let received = String::from_utf8_lossy(&buf[..len]).into_owned();
+            assert!(received.starts_with(priority));
+            assert!(received.ends_with(&format!(" memo-test[{}]: hello", process::id())));
+        }
+        fs::remove_file(&path).unwrap();
+
+        assert!(Syslog::new(&target, "bogus", "memo-test").is_err());
+        assert!(Syslog::new("ftp://example.com", "user", "memo-test").is_err());
+    }
+
+    #[test]
+    fn test_format_remote() {
+        let syslog = Syslog::new("local", "local0", "globalip-memo").unwrap();
+        let message = syslog.format_remote(Level::Warn, "hello");
+        assert!(message.starts_with("<132>1 "));
+        assert!(message.ends_with(&format!(" globalip-memo {} - - hello", process::id())));
+    }
+}
@@ -0,0 +1,165 @@
+use crate::env::TlsOptions;
+use crate::error::{PinMismatch, Result};
+use openssl::base64;
+use openssl::pkcs12::Pkcs12;
+use openssl::pkey::{PKey, Private};
+use openssl::sha::sha256;
+use openssl::ssl::{HandshakeError, SslConnector, SslMethod, SslStream};
+use openssl::x509::store::X509StoreBuilder;
+use openssl::x509::X509;
+use std::fs;
+use std::net::TcpStream;
+use std::path::Path;
+
+const IDENTITY_NAME: &str = "globalip-memo";
+const IDENTITY_PASSWORD: &str = "globalip-memo";
+const PIN_PREFIX: &str = "sha256/";
+
+fn read_file(path: &Path) -> Result<Vec<u8>> {
+    fs::read(path).map_err(|e| err_io!(e, "read_file: Failed to read {}", path.display()))
+}
+
+fn load_ca_certs(tls: &TlsOptions) -> Result<Vec<X509>> {
+    match tls.ca_file() {
+        Some(path) => X509::stack_from_pem(&read_file(path)?).map_err(|e| {
+            err_tls!(e, "load_ca_certs: Failed to parse CA bundle - {}", path.display())
+        }),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn load_identity(tls: &TlsOptions) -> Result<Option<(X509, PKey<Private>)>> {
+    let cert_path = match tls.client_cert() {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let cert_pem = read_file(cert_path)?;
+    let key_path = tls.client_key().unwrap_or(cert_path);
+    let key_pem = read_file(key_path)?;
+    let cert = X509::from_pem(&cert_pem).map_err(|e| {
+        err_tls!(e, "load_identity: Failed to parse client certificate - {}", cert_path.display())
+    })?;
+    let key = PKey::private_key_from_pem(&key_pem).map_err(|e| {
+        err_tls!(e, "load_identity: Failed to parse client key - {}", key_path.display())
+    })?;
+    Ok(Some((cert, key)))
+}
+
+/// Whether the options restrict the trusted keys beyond what the reqwest clients can enforce.
+pub fn needs_verification(tls: &TlsOptions) -> bool {
+    !tls.system_roots() || !tls.pins().is_empty()
+}
+
+/// Root certificates and client identity to install on the reqwest client builders.
+pub fn client_certs(tls: &TlsOptions) -> Result<(Vec<reqwest::Certificate>, Option<reqwest::Identity>)> {
+    let mut certs = Vec::new();
+    for cert in load_ca_certs(tls)? {
+        let der = cert
+            .to_der()
+            .map_err(|e| err_tls!(e, "client_certs: Failed to encode CA certificate"))?;
+        let cert = reqwest::Certificate::from_der(&der)
+            .map_err(|e| err_http!(e, "client_certs: Invalid CA certificate"))?;
+        certs.push(cert);
+    }
+    let identity = match load_identity(tls)? {
+        Some((cert, key)) => {
+            let der = Pkcs12::builder()
+                .name(IDENTITY_NAME)
+                .pkey(&key)
+                .cert(&cert)
+                .build2(IDENTITY_PASSWORD)
+                .and_then(|pkcs12| pkcs12.to_der())
+                .map_err(|e| err_tls!(e, "client_certs: Failed to build client identity"))?;
+            let identity = reqwest::Identity::from_pkcs12_der(&der, IDENTITY_PASSWORD)
+                .map_err(|e| err_http!(e, "client_certs: Invalid client identity"))?;
+            Some(identity)
+        }
+        None => None,
+    };
+    Ok((certs, identity))
+}
+
+fn normalize_pin(pin: &str) -> &str {
+    pin.strip_prefix(PIN_PREFIX).unwrap_or(pin)
+}
+
+/// Connector trusting the CA bundle, plus the system roots unless disabled, and presenting the client identity.
+pub fn connector(tls: Option<&TlsOptions>, url: &str) -> Result<SslConnector> {
+    let mut builder = SslConnector::builder(SslMethod::tls())
+        .map_err(|e| err_tls!(e, "connector: Failed to create TLS connector - {}", url))?;
+    let tls = match tls {
+        Some(tls) => tls,
+        None => return Ok(builder.build()),
+    };
+    let certs = load_ca_certs(tls)?;
+    if tls.system_roots() {
+        for cert in certs {
+            builder
+                .cert_store_mut()
+                .add_cert(cert)
+                .map_err(|e| err_tls!(e, "connector: Failed to add CA certificate - {}", url))?;
+        }
+    } else {
+        let mut store = X509StoreBuilder::new()
+            .map_err(|e| err_tls!(e, "connector: Failed to create certificate store - {}", url))?;
+        for cert in certs {
+            store
+                .add_cert(cert)
+                .map_err(|e| err_tls!(e, "connector: Failed to add CA certificate - {}", url))?;
+        }
+        builder.set_cert_store(store.build());
+    }
+    if let Some((cert, key)) = load_identity(tls)? {
+        builder
+            .set_certificate(&cert)
+            .and_then(|_| builder.set_private_key(&key))
+            .map_err(|e| err_tls!(e, "connector: Failed to set client identity - {}", url))?;
+    }
+    Ok(builder.build())
+}
+
+/// Runs the handshake on the stream and checks the peer chain against the pins, if any.
+pub fn handshake(
+    connector: &SslConnector,
+    host: &str,
+    stream: TcpStream,
+    pins: &[String],
+    url: &str,
+) -> Result<SslStream<TcpStream>> {
+    let stream = connector.connect(host, stream).map_err(|e| match e {
+        HandshakeError::SetupFailure(e) => {
+            err_tls!(e, "handshake: Failed to set up TLS handshake - {}", url)
+        }
+        HandshakeError::Failure(s) | HandshakeError::WouldBlock(s) => {
+            err_ssl!(s.into_error(), "handshake: TLS handshake failed - {}", url)
+        }
+    })?;
+    debug!("handshake: Certificate chain verified - {}", url);
+    if pins.is_empty() {
+        return Ok(stream);
+    }
+
+    let mut found = Vec::new();
+    if let Some(chain) = stream.ssl().peer_cert_chain() {
+        for cert in chain {
+            let spki = cert
+                .public_key()
+                .and_then(|key| key.public_key_to_der())
+                .map_err(|e| err_tls!(e, "handshake: Failed to encode public key - {}", url))?;
+            found.push(base64::encode_block(&sha256(&spki)));
+        }
+    }
+    if found
+        .iter()
+        .any(|hash| pins.iter().any(|pin| normalize_pin(pin) == hash))
+    {
+        debug!("handshake: Public key pin matched - {}", url);
+        Ok(stream)
+    } else {
+        Err(err_pin!(
+            PinMismatch::new(host, found),
+            "handshake: Public key pin mismatch - {}",
+            url
+        ))
+    }
+}